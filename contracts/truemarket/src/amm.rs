@@ -0,0 +1,436 @@
+//! Market maker math: the fixed product market maker (FPMM) and Hanson's logarithmic
+//! market scoring rule (LMSR).
+//!
+//! Shared by the contract and the service so that quotes always match what a trade
+//! executes. Every function works on the `shares_available` pools of a market and
+//! returns `None` when the trade can't be priced (overflow, missing outcome or not
+//! enough liquidity).
+
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
+
+use crate::{Fees, FEE_DENOMINATOR, ONE};
+
+/// Prices trades against a market's pools. A buy of `amount` mints that many complete
+/// sets into the pools and hands out shares of one outcome; a sell does the reverse.
+pub trait PricingEngine {
+    /// Shares of `outcome_id` received when `amount` is added to every pool.
+    fn buy_amount(&self, pools: &[u128], amount: u128, outcome_id: u32) -> Option<u128>;
+    /// Shares of `outcome_id` that must be sold so that `amount` can be taken out of every pool.
+    fn sell_amount(&self, pools: &[u128], amount: u128, outcome_id: u32) -> Option<u128>;
+    /// Implied probability of each outcome (scaled by `ONE`).
+    fn prices(&self, pools: &[u128]) -> Vec<u128>;
+}
+
+/// The market maker a market was created with.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum MarketMaker {
+    Fpmm,
+    Lmsr { b: u128 },
+}
+
+impl PricingEngine for MarketMaker {
+    fn buy_amount(&self, pools: &[u128], amount: u128, outcome_id: u32) -> Option<u128> {
+        match self {
+            MarketMaker::Fpmm => Fpmm.buy_amount(pools, amount, outcome_id),
+            MarketMaker::Lmsr { b } => Lmsr { b: *b }.buy_amount(pools, amount, outcome_id),
+        }
+    }
+
+    fn sell_amount(&self, pools: &[u128], amount: u128, outcome_id: u32) -> Option<u128> {
+        match self {
+            MarketMaker::Fpmm => Fpmm.sell_amount(pools, amount, outcome_id),
+            MarketMaker::Lmsr { b } => Lmsr { b: *b }.sell_amount(pools, amount, outcome_id),
+        }
+    }
+
+    fn prices(&self, pools: &[u128]) -> Vec<u128> {
+        match self {
+            MarketMaker::Fpmm => Fpmm.prices(pools),
+            MarketMaker::Lmsr { b } => Lmsr { b: *b }.prices(pools),
+        }
+    }
+}
+
+/// Constant-product pools: the product of all pools never decreases.
+pub struct Fpmm;
+
+impl PricingEngine for Fpmm {
+    fn buy_amount(&self, pools: &[u128], amount: u128, outcome_id: u32) -> Option<u128> {
+        calc_buy_amount(pools, amount, outcome_id)
+    }
+
+    fn sell_amount(&self, pools: &[u128], amount: u128, outcome_id: u32) -> Option<u128> {
+        calc_sell_amount(pools, amount, outcome_id)
+    }
+
+    fn prices(&self, pools: &[u128]) -> Vec<u128> {
+        calc_prices(pools)
+    }
+}
+
+/// Hanson's LMSR with liquidity parameter `b`, cost `C(q) = b ln(sum exp(q_i / b))`.
+///
+/// The pools are the market maker's inventory, so the outstanding shares are
+/// `q_i = K - pool_i` for some `K` shared by all outcomes. `C` only shifts with `K`,
+/// which lets every formula below work on `-pool_i` directly. Starting with every pool
+/// at least `max_loss` keeps the pools from ever running dry.
+pub struct Lmsr {
+    pub b: u128,
+}
+
+impl Lmsr {
+    /// Worst-case loss of the market maker, `b ln(N)`, rounded up.
+    pub fn max_loss(&self, outcome_count: u32) -> u128 {
+        (self.b as f64 * (outcome_count as f64).ln()).ceil() as u128 + 1
+    }
+
+    /// `exp(-pool_i / b)`, shifted so the largest weight is 1.
+    fn weights(&self, pools: &[u128]) -> Vec<f64> {
+        let min = pools.iter().copied().min().unwrap_or(0);
+        let b = self.b as f64;
+        pools.iter().map(|pool| (-((pool - min) as f64) / b).exp()).collect()
+    }
+
+    /// Splits the weights into the traded outcome's and the sum of all the others.
+    fn split(&self, pools: &[u128], outcome_id: u32) -> Option<(f64, f64)> {
+        if self.b == 0 {
+            return None;
+        }
+        let weights = self.weights(pools);
+        let own = *weights.get(outcome_id as usize)?;
+        let rest = weights.iter().sum::<f64>() - own;
+        (own > 0.0).then_some((own, rest))
+    }
+}
+
+impl PricingEngine for Lmsr {
+    fn buy_amount(&self, pools: &[u128], amount: u128, outcome_id: u32) -> Option<u128> {
+        let (own, rest) = self.split(pools, outcome_id)?;
+        let b = self.b as f64;
+
+        // Solves C(q + shares e_i) - C(q) = amount:
+        // shares = amount + b ln((own + rest - rest exp(-amount / b)) / own)
+        let extra = b * ((own + rest - rest * (-(amount as f64) / b).exp()) / own).ln();
+        let shares = amount.checked_add(to_units(extra, b, false)?)?;
+        (shares <= pools[outcome_id as usize].checked_add(amount)?).then_some(shares)
+    }
+
+    fn sell_amount(&self, pools: &[u128], amount: u128, outcome_id: u32) -> Option<u128> {
+        let (own, rest) = self.split(pools, outcome_id)?;
+        let b = self.b as f64;
+
+        // Solves C(q) - C(q - shares e_i) = amount:
+        // shares = amount - b ln((own + rest - rest exp(amount / b)) / own)
+        let remaining = own + rest - rest * (amount as f64 / b).exp();
+        if remaining <= 0.0 {
+            return None;
+        }
+        let shares = amount.checked_add(to_units(-b * (remaining / own).ln(), b, true)?)?;
+
+        let drained = pools.iter().enumerate().any(|(i, pool)| {
+            if i as u32 == outcome_id { pool.saturating_add(shares) < amount } else { *pool <= amount }
+        });
+        (!drained).then_some(shares)
+    }
+
+    fn prices(&self, pools: &[u128]) -> Vec<u128> {
+        if pools.is_empty() || self.b == 0 {
+            return Vec::new();
+        }
+        let weights = self.weights(pools);
+        let total: f64 = weights.iter().sum();
+        weights.iter().map(|weight| (weight / total * ONE as f64) as u128).collect()
+    }
+}
+
+/// Converts a float amount to units, rounding in the market maker's favour. The float
+/// error of `b ln(x)` grows with `b`, so the margin does too.
+fn to_units(value: f64, b: f64, round_up: bool) -> Option<u128> {
+    if !value.is_finite() {
+        return None;
+    }
+    let margin = b * 1e-12 + value.abs() * 1e-12 + 1.0;
+    let units = if round_up { (value + margin).ceil() } else { (value - margin).floor() };
+    (units < u128::MAX as f64).then_some(units.max(0.0) as u128)
+}
+
+/// Shares and fees of a trade priced against a set of pools.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TradeQuote {
+    pub shares: u128,
+    pub fee: u128,
+    pub treasury_fee: u128,
+    pub distributor_fee: u128,
+    /// Collateral added to (buy) or taken out of (sell) every pool.
+    pub pool_amount: u128,
+}
+
+/// Prices a buy of `outcome_id` for `value`, fees included.
+pub fn quote_buy(
+    engine: &impl PricingEngine,
+    pools: &[u128],
+    fees: &Fees,
+    outcome_id: u32,
+    value: u128,
+) -> Option<TradeQuote> {
+    let (fee, treasury_fee, distributor_fee) = fees.split(value);
+    let pool_amount = value
+        .checked_sub(fee)?
+        .checked_sub(treasury_fee)?
+        .checked_sub(distributor_fee)?;
+
+    // Only the value that actually enters the pools buys shares
+    let shares = engine.buy_amount(pools, pool_amount, outcome_id)?;
+    Some(TradeQuote { shares, fee, treasury_fee, distributor_fee, pool_amount })
+}
+
+/// Prices a sell of `outcome_id` that pays the seller `value` after fees.
+pub fn quote_sell(
+    engine: &impl PricingEngine,
+    pools: &[u128],
+    fees: &Fees,
+    outcome_id: u32,
+    value: u128,
+) -> Option<TradeQuote> {
+    // Fees are charged on top of the value the seller receives
+    let gross = value.checked_mul(FEE_DENOMINATOR)? / (FEE_DENOMINATOR - fees.total());
+    let (fee, treasury_fee, distributor_fee) = fees.split(gross);
+    let pool_amount = value
+        .checked_add(fee)?
+        .checked_add(treasury_fee)?
+        .checked_add(distributor_fee)?;
+
+    let shares = engine.sell_amount(pools, pool_amount, outcome_id)?;
+    Some(TradeQuote { shares, fee, treasury_fee, distributor_fee, pool_amount })
+}
+
+/// The pools after `quote` was bought: every pool grows, the bought shares leave.
+pub fn pools_after_buy(pools: &[u128], outcome_id: u32, quote: &TradeQuote) -> Vec<u128> {
+    pools
+        .iter()
+        .enumerate()
+        .map(|(i, pool)| {
+            let pool = pool + quote.pool_amount;
+            if i as u32 == outcome_id { pool - quote.shares } else { pool }
+        })
+        .collect()
+}
+
+/// The pools after `quote` was sold: the sold shares return, every pool shrinks.
+pub fn pools_after_sell(pools: &[u128], outcome_id: u32, quote: &TradeQuote) -> Vec<u128> {
+    pools
+        .iter()
+        .enumerate()
+        .map(|(i, pool)| {
+            let pool = if i as u32 == outcome_id { pool + quote.shares } else { *pool };
+            pool - quote.pool_amount
+        })
+        .collect()
+}
+
+/// Shares of `outcome_id` received when `amount` is added to every pool.
+pub fn calc_buy_amount(pools: &[u128], amount: u128, outcome_id: u32) -> Option<u128> {
+    let buy_pool = *pools.get(outcome_id as usize)?;
+    let mut ending_balance = BigUint::from(buy_pool);
+
+    for (i, pool) in pools.iter().enumerate() {
+        if i as u32 != outcome_id {
+            let shares = BigUint::from(*pool);
+            let denom = &shares + BigUint::from(amount);
+            let num = &ending_balance * &shares;
+            ending_balance = (num + &denom - BigUint::from(1u32)) / denom;
+        }
+    }
+    let ending = ending_balance.to_u128()?;
+    buy_pool.checked_add(amount)?.checked_sub(ending)
+}
+
+/// Inverse of `calc_buy_amount`: how many `outcome_id` shares must be sold so that
+/// `amount` can be taken out of every pool.
+pub fn calc_sell_amount(pools: &[u128], amount: u128, outcome_id: u32) -> Option<u128> {
+    let sell_pool = *pools.get(outcome_id as usize)?;
+    let mut ending_balance = BigUint::from(sell_pool);
+
+    for (i, pool) in pools.iter().enumerate() {
+        if i as u32 != outcome_id {
+            if *pool <= amount {
+                return None;
+            }
+            let shares = BigUint::from(*pool);
+            let denom = &shares - BigUint::from(amount);
+            let num = &ending_balance * &shares;
+            ending_balance = (num + &denom - BigUint::from(1u32)) / denom;
+        }
+    }
+    let ending = ending_balance.to_u128()?;
+    amount.checked_add(ending)?.checked_sub(sell_pool)
+}
+
+/// `a * b / denom` without intermediate overflow.
+pub fn mul_div(a: u128, b: u128, denom: u128) -> Option<u128> {
+    if denom == 0 {
+        return None;
+    }
+    (BigUint::from(a) * BigUint::from(b) / BigUint::from(denom)).to_u128()
+}
+
+/// Implied probability of each outcome (scaled by `ONE`). An outcome's price is
+/// inversely proportional to its pool: `(1 / pool_i) / sum(1 / pool_k)`.
+pub fn calc_prices(pools: &[u128]) -> Vec<u128> {
+    let count = pools.len();
+    if count == 0 {
+        return Vec::new();
+    }
+    if pools.contains(&0) {
+        return vec![ONE / count as u128; count];
+    }
+
+    // prod_{j != i} pool_j for every outcome i
+    let weights: Vec<BigUint> = (0..count)
+        .map(|i| {
+            pools.iter().enumerate()
+                .filter(|(j, _)| *j != i)
+                .fold(BigUint::from(1u32), |acc, (_, pool)| acc * *pool)
+        })
+        .collect();
+    let total: BigUint = weights.iter().sum();
+
+    weights
+        .iter()
+        .map(|weight| (weight * BigUint::from(ONE) / &total).to_u128().unwrap_or(ONE))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fees(fee: u64, treasury_fee: u64, distributor_fee: u64) -> Fees {
+        Fees { fee, treasury_fee, distributor_fee }
+    }
+
+    fn pool_sets() -> Vec<Vec<u128>> {
+        vec![
+            vec![10 * ONE, 10 * ONE],
+            vec![3 * ONE, 7 * ONE, 20 * ONE],
+            vec![ONE, 2 * ONE, 5 * ONE, 50 * ONE],
+        ]
+    }
+
+    #[test]
+    fn fpmm_sell_inverts_buy() {
+        for pools in pool_sets() {
+            for outcome_id in 0..pools.len() as u32 {
+                let amount = 2 * ONE + 12_345;
+                let bought = calc_buy_amount(&pools, amount, outcome_id).unwrap();
+                let quote = TradeQuote { shares: bought, fee: 0, treasury_fee: 0, distributor_fee: 0, pool_amount: amount };
+                let after = pools_after_buy(&pools, outcome_id, &quote);
+
+                // Both directions round against the trader; what is left is rounding noise
+                let sold = calc_sell_amount(&after, amount, outcome_id).unwrap();
+                assert!(sold >= bought);
+                assert!((sold - bought) * 1_000_000_000_000 <= bought);
+            }
+        }
+    }
+
+    #[test]
+    fn fpmm_sell_rejects_draining_a_pool() {
+        assert_eq!(calc_sell_amount(&[10 * ONE, 4 * ONE], 4 * ONE, 0), None);
+        assert_eq!(calc_buy_amount(&[10 * ONE, 4 * ONE], ONE, 2), None);
+    }
+
+    #[test]
+    fn quote_sell_pays_exactly_value_after_fees() {
+        let pools = vec![10 * ONE, 10 * ONE, 10 * ONE];
+        for fees in [fees(0, 0, 0), fees(100, 50, 25), fees(200, 0, 150)] {
+            for value in [1, 999, ONE, 3 * ONE + 7] {
+                let quote = quote_sell(&Fpmm, &pools, &fees, 1, value).unwrap();
+                let charged = quote.fee + quote.treasury_fee + quote.distributor_fee;
+                assert_eq!(quote.pool_amount - charged, value);
+
+                // The fees are the configured rates of the gross amount, up to rounding
+                let (fee, treasury_fee, distributor_fee) = fees.split(quote.pool_amount);
+                assert!(fee.abs_diff(quote.fee) <= 1);
+                assert!(treasury_fee.abs_diff(quote.treasury_fee) <= 1);
+                assert!(distributor_fee.abs_diff(quote.distributor_fee) <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn quote_buy_only_pools_value_after_fees() {
+        let fees = fees(100, 50, 25);
+        let quote = quote_buy(&Fpmm, &[10 * ONE, 10 * ONE], &fees, 0, 4 * ONE).unwrap();
+        assert_eq!(quote.pool_amount + quote.fee + quote.treasury_fee + quote.distributor_fee, 4 * ONE);
+        assert_eq!(quote.shares, calc_buy_amount(&[10 * ONE, 10 * ONE], quote.pool_amount, 0).unwrap());
+    }
+
+    #[test]
+    fn fpmm_prices_sum_to_one() {
+        for pools in pool_sets() {
+            let prices = calc_prices(&pools);
+            let total: u128 = prices.iter().sum();
+            assert!(ONE - total <= pools.len() as u128);
+            // The thinnest pool is the most likely outcome
+            assert!(prices.windows(2).all(|pair| pair[0] >= pair[1]));
+        }
+        assert_eq!(calc_prices(&[ONE, 0]), vec![ONE / 2, ONE / 2]);
+    }
+
+    #[test]
+    fn lmsr_round_trip_never_pays_out_more() {
+        let lmsr = Lmsr { b: 10 * ONE };
+        for pools in [vec![lmsr.max_loss(2); 2], vec![20 * ONE, 35 * ONE, 60 * ONE]] {
+            for outcome_id in 0..pools.len() as u32 {
+                for amount in [1_000, ONE / 3, 5 * ONE, 40 * ONE] {
+                    let bought = lmsr.buy_amount(&pools, amount, outcome_id).unwrap();
+                    let quote = TradeQuote { shares: bought, fee: 0, treasury_fee: 0, distributor_fee: 0, pool_amount: amount };
+                    let after = pools_after_buy(&pools, outcome_id, &quote);
+
+                    // Getting the same amount back takes at least the shares just bought
+                    let sold = lmsr.sell_amount(&after, amount, outcome_id).unwrap();
+                    assert!(sold >= bought);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lmsr_prices_sum_to_one() {
+        let lmsr = Lmsr { b: 10 * ONE };
+        for pools in [vec![ONE; 2], vec![20 * ONE, 35 * ONE, 60 * ONE], vec![ONE, 500 * ONE]] {
+            let total: u128 = lmsr.prices(&pools).iter().sum();
+            assert!(ONE.abs_diff(total) <= 1_000);
+        }
+        assert_eq!(Lmsr { b: 0 }.prices(&[ONE, ONE]), Vec::<u128>::new());
+    }
+
+    #[test]
+    fn lmsr_pools_survive_the_loss_bound() {
+        for outcome_count in [2, 3, 8] {
+            let lmsr = Lmsr { b: 7 * ONE };
+            let mut pools = vec![lmsr.max_loss(outcome_count); outcome_count as usize];
+
+            // Keep buying the same outcome until its price is all but certain
+            for amount in [ONE, 10 * ONE, 100 * ONE, 1_000 * ONE, 10_000 * ONE] {
+                let shares = lmsr.buy_amount(&pools, amount, 0).unwrap();
+                assert!(shares <= pools[0] + amount);
+                let quote = TradeQuote { shares, fee: 0, treasury_fee: 0, distributor_fee: 0, pool_amount: amount };
+                pools = pools_after_buy(&pools, 0, &quote);
+            }
+            assert!(lmsr.prices(&pools)[0] > ONE - ONE / 1_000);
+        }
+    }
+
+    #[test]
+    fn lmsr_sell_rejects_draining_a_pool() {
+        let lmsr = Lmsr { b: 10 * ONE };
+        let pools = vec![20 * ONE, 8 * ONE];
+        assert_eq!(lmsr.sell_amount(&pools, 8 * ONE, 0), None);
+        assert_eq!(lmsr.sell_amount(&pools, 50 * ONE, 1), None);
+        assert!(lmsr.sell_amount(&pools, ONE, 0).is_some());
+    }
+}
@@ -5,21 +5,28 @@ mod state;
 use linera_sdk::{
     contract::ContractRuntime,
     linera_base_types::{
-        AccountOwner, Amount, ApplicationId, ChainId, Timestamp, WithContractAbi, Account as FungibleAccount
+        AccountOwner, Amount, ApplicationId, ChainId, StreamName, StreamUpdate, TimeDelta,
+        Timestamp, WithContractAbi, Account as FungibleAccount
     },
     views::{RootView, View},
     Contract,
 };
 
-use num_bigint::BigUint;
-use num_traits::ToPrimitive;
-
+use truemarket::amm::{self, Lmsr, MarketMaker, PricingEngine, TradeQuote};
+use truemarket::index::{close_prefix, creator_prefix, index_key, state_prefix, token_prefix};
+use truemarket::{liquidity, neg_risk};
 use truemarket::{
-    Fees, Message, Operation, TruemarketAbi, MarketState, MAX_FEE, MAX_OUTCOMES,
-    MINIMUM_REALITIO_TIMEOUT, FEE_DENOMINATOR,
+    FeeBreakdown, Fees, Message, Operation, TradeResult, TruemarketAbi, TruemarketEvent,
+    TruemarketResponse, MarketAction, MarketState, ParentPosition, ScalarRange, TradeAction, UpdateKind,
+    UpdateMarketParams,
+    MARKET_STREAM, MAX_FEE, MAX_OUTCOMES, MAX_OUTCOME_LABEL_LENGTH, MINIMUM_REALITIO_TIMEOUT, ONE,
+    OUTCOME_NO, OUTCOME_YES,
 };
 
-use self::state::{Market, MarketOutcome, TruemarketState};
+use self::state::{
+    Answer, AuditEntry, Event, Market, MarketOutcome, MarketUpdate, Question, TradeFailure, TradeRecord,
+    TruemarketState,
+};
 
 pub struct TruemarketContract {
     state: TruemarketState,
@@ -36,7 +43,7 @@ impl Contract for TruemarketContract {
     type Message = Message;
     type Parameters = ();
     type InstantiationArgument = ();
-    type EventValue = ();
+    type EventValue = TruemarketEvent;
 
     async fn load(runtime: ContractRuntime<Self>) -> Self {
         let state = TruemarketState::load(runtime.root_view_storage_context())
@@ -55,13 +62,16 @@ impl Contract for TruemarketContract {
 
         match operation {
             Operation::CreateMarket {
-                value, closes_at, outcomes, token, distribution, question, image,
-                arbitrator, buy_fees, sell_fees, treasury, distributor, realitio_timeout, manager,
+                value, closes_at, outcomes, token, distribution, lmsr_liquidity, scalar_range, parent,
+                question, image, arbitrator, buy_fees, sell_fees, treasury, distributor, realitio_timeout,
+                manager,
             } => {
-                self.create_market(
-                    value, closes_at, outcomes, token, distribution, question, image,
-                    arbitrator, buy_fees, sell_fees, treasury, distributor, realitio_timeout, manager,
+                let market_id = self.create_market(
+                    value, closes_at, outcomes, token, distribution, lmsr_liquidity, scalar_range, parent,
+                    question, image, arbitrator, buy_fees, sell_fees, treasury, distributor, realitio_timeout,
+                    manager,
                 ).await;
+                TruemarketResponse::MarketCreated { market_id }
             }
             Operation::Buy {
                 market_id,
@@ -76,7 +86,7 @@ impl Contract for TruemarketContract {
                     // LOCAL BUY (Same Chain)
                     // The `token` param is ignored here in favor of the one in state, 
                     // but we could verify they match if we wanted.
-                    self.buy(
+                    let trade = self.buy(
                         market_id,
                         outcome_id,
                         min_outcome_shares_to_buy,
@@ -84,6 +94,7 @@ impl Contract for TruemarketContract {
                         value,
                         current_chain_id, // Receipt goes to self
                     ).await;
+                    TruemarketResponse::Bought(trade)
                 } else {
                     // REMOTE BUY (User Chain -> Market Chain)
                     // We MUST use the `token` passed in arguments because we might not have state
@@ -97,12 +108,200 @@ impl Contract for TruemarketContract {
                         current_chain_id, // Return chain ID
                         token, // Pass the token ID explicitly
                     ).await;
+                    TruemarketResponse::Forwarded
+                }
+            }
+            Operation::Sell {
+                market_id,
+                outcome_id,
+                value,
+                max_outcome_shares_to_sell,
+            } => {
+                let seller = self.runtime.authenticated_signer().expect("Authenticated signer required");
+
+                if current_chain_id == market_chain_id {
+                    let trade = self.sell(
+                        market_id,
+                        outcome_id,
+                        value,
+                        max_outcome_shares_to_sell,
+                        seller,
+                        current_chain_id,
+                    ).await;
+                    TruemarketResponse::Sold(trade)
+                } else {
+                    // REMOTE SELL: shares live on the market chain, proceeds come back here
+                    let message = Message::Sell {
+                        market_id,
+                        outcome_id,
+                        value,
+                        max_outcome_shares_to_sell,
+                        owner: seller,
+                        return_chain_id: current_chain_id,
+                    };
+                    self.send_tracked(message, market_chain_id);
+                    TruemarketResponse::Forwarded
+                }
+            }
+            Operation::CloseMarket { market_id } => {
+                self.assert_market_chain();
+                self.close_market(market_id).await;
+                TruemarketResponse::Ok
+            }
+            Operation::ResolveMarket { market_id, outcome_id } => {
+                let caller = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                self.resolve_market(market_id, outcome_id, caller).await;
+                TruemarketResponse::Ok
+            }
+            Operation::ResolveScalarMarket { market_id, value } => {
+                let caller = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                self.resolve_scalar_market(market_id, value, caller).await;
+                TruemarketResponse::Ok
+            }
+            Operation::ClaimWinnings { market_id } => {
+                let owner = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                if current_chain_id == market_chain_id {
+                    let amount = self.claim_winnings(market_id, owner, current_chain_id).await;
+                    TruemarketResponse::Claimed { amount }
+                } else {
+                    let message = Message::Claim {
+                        market_id,
+                        owner,
+                        return_chain_id: current_chain_id,
+                    };
+                    self.send_tracked(message, market_chain_id);
+                    TruemarketResponse::Forwarded
+                }
+            }
+            Operation::VoidMarket { market_id, equal_split } => {
+                let caller = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                self.void_market(market_id, equal_split, caller).await;
+                TruemarketResponse::Ok
+            }
+            Operation::ClaimVoidedShares { market_id } => {
+                let owner = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                if current_chain_id == market_chain_id {
+                    let amount = self.claim_payouts(market_id, owner, current_chain_id).await;
+                    TruemarketResponse::Claimed { amount }
+                } else {
+                    let message = Message::Claim {
+                        market_id,
+                        owner,
+                        return_chain_id: current_chain_id,
+                    };
+                    self.send_tracked(message, market_chain_id);
+                    TruemarketResponse::Forwarded
                 }
             }
+            Operation::AddLiquidity { market_id, value } => {
+                let provider = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                let shares = self.add_liquidity(market_id, value, provider).await;
+                TruemarketResponse::LiquidityAdded { shares }
+            }
+            Operation::RemoveLiquidity { market_id, shares } => {
+                let provider = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                let value = self.remove_liquidity(market_id, shares, provider).await;
+                TruemarketResponse::LiquidityRemoved { value }
+            }
+            Operation::ClaimFees { market_id } => {
+                let provider = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                let amount = self.claim_fees(market_id, provider).await;
+                TruemarketResponse::FeesClaimed { amount }
+            }
+            Operation::SplitPosition { market_id, value } => {
+                let owner = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                self.split_position(market_id, value, owner).await;
+                TruemarketResponse::Ok
+            }
+            Operation::MergePositions { market_id, shares } => {
+                let owner = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                self.merge_positions(market_id, shares, owner).await;
+                TruemarketResponse::Ok
+            }
+            Operation::PauseMarket { market_id } => {
+                let caller = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                self.set_paused(market_id, true, caller).await;
+                TruemarketResponse::Ok
+            }
+            Operation::UnpauseMarket { market_id } => {
+                let caller = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                self.set_paused(market_id, false, caller).await;
+                TruemarketResponse::Ok
+            }
+            Operation::UpdateMarket { market_id, params } => {
+                let caller = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                self.update_market(market_id, params, caller).await;
+                TruemarketResponse::Ok
+            }
+            Operation::SubmitAnswer { market_id, outcome_id, bond } => {
+                let answerer = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                self.submit_answer(market_id, outcome_id, bond, answerer).await;
+                TruemarketResponse::Ok
+            }
+            Operation::FinalizeAnswer { market_id } => {
+                self.assert_market_chain();
+                self.finalize_answer(market_id).await;
+                TruemarketResponse::Ok
+            }
+            Operation::RequestArbitration { market_id, fee } => {
+                let requester = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                self.request_arbitration(market_id, fee, requester).await;
+                TruemarketResponse::Ok
+            }
+            Operation::SubscribeToMarkets => {
+                // The market chain records its own events as it emits them
+                assert_ne!(current_chain_id, market_chain_id, "Operation only valid on user chains");
+                let application_id = self.runtime.application_id().forget_abi();
+                self.runtime.subscribe_to_events(market_chain_id, application_id, StreamName::from(MARKET_STREAM));
+                TruemarketResponse::Ok
+            }
+            Operation::UnsubscribeFromMarkets => {
+                assert_ne!(current_chain_id, market_chain_id, "Operation only valid on user chains");
+                let application_id = self.runtime.application_id().forget_abi();
+                self.runtime.unsubscribe_from_events(market_chain_id, application_id, StreamName::from(MARKET_STREAM));
+                TruemarketResponse::Ok
+            }
+            Operation::CreateEvent { title, closes_at, market_ids } => {
+                let manager = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                let event_id = self.create_event(title, closes_at, market_ids, manager).await;
+                TruemarketResponse::EventCreated { event_id }
+            }
+            Operation::ResolveEvent { event_id, market_id } => {
+                let caller = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                self.resolve_event(event_id, market_id, caller).await;
+                TruemarketResponse::Ok
+            }
+            Operation::ConvertPositions { event_id, market_ids, shares } => {
+                let owner = self.runtime.authenticated_signer().expect("Authenticated signer required");
+                self.assert_market_chain();
+                let value = self.convert_positions(event_id, market_ids, shares, owner).await;
+                TruemarketResponse::Converted { value }
+            }
         }
     }
 
     async fn execute_message(&mut self, message: Self::Message) {
+        if self.runtime.message_is_bouncing() == Some(true) {
+            // Runs on User Chain: the market chain rejected one of our tracked messages
+            self.handle_bounce(message).await;
+            return;
+        }
+
         match message {
             Message::Buy {
                 market_id,
@@ -111,6 +310,7 @@ impl Contract for TruemarketContract {
                 owner,
                 value,
                 return_chain_id,
+                token,
             } => {
                 // Runs on Market Chain
                 assert_eq!(
@@ -119,6 +319,13 @@ impl Contract for TruemarketContract {
                     "Message only valid on market creator chain"
                 );
 
+                // The tokens pushed in buy_remote must be the ones the market trades in
+                let market = self.state.markets.get(&market_id).await
+                    .expect("State error").expect("Market not found");
+                assert_eq!(market.token, token, "Token mismatch");
+                // Its collateral is a position on this chain, which can't be pushed from elsewhere
+                assert!(market.parent.is_none(), "Conditional markets trade on the market chain");
+
                 self.buy(
                     market_id,
                     outcome_id,
@@ -132,6 +339,7 @@ impl Contract for TruemarketContract {
                 market_id,
                 outcome_id,
                 amount,
+                label,
             } => {
                 // Runs on User Chain (Receipt)
                 let market_chain_id = self.runtime.application_creator_chain_id();
@@ -146,7 +354,97 @@ impl Contract for TruemarketContract {
                 let current_shares = self.state.my_shares.get(&key).await.expect("View error").unwrap_or(0);
                 
                 let new_total = current_shares.checked_add(amount).expect("Share overflow");
-                self.state.my_shares.insert(&key, new_total).expect("Failed to save local shares");
+                self.set_my_shares(market_id, outcome_id, new_total);
+                self.state.outcome_labels.insert(&key, label).expect("Failed to save outcome label");
+            }
+            Message::Sell {
+                market_id,
+                outcome_id,
+                value,
+                max_outcome_shares_to_sell,
+                owner,
+                return_chain_id,
+            } => {
+                // Runs on Market Chain
+                self.assert_market_chain();
+                assert_eq!(self.runtime.authenticated_signer(), Some(owner), "Incorrect authentication");
+
+                self.sell(
+                    market_id,
+                    outcome_id,
+                    value,
+                    max_outcome_shares_to_sell,
+                    owner,
+                    return_chain_id,
+                ).await;
+            }
+            Message::Claim {
+                market_id,
+                owner,
+                return_chain_id,
+            } => {
+                // Runs on Market Chain
+                self.assert_market_chain();
+                assert_eq!(self.runtime.authenticated_signer(), Some(owner), "Incorrect authentication");
+
+                let market = self.state.markets.get(&market_id).await
+                    .expect("State error").expect("Market not found");
+                match market.state {
+                    MarketState::Resolved => {
+                        self.claim_winnings(market_id, owner, return_chain_id).await;
+                    }
+                    MarketState::Voided => {
+                        self.claim_payouts(market_id, owner, return_chain_id).await;
+                    }
+                    _ => panic!("Market not resolved"),
+                }
+            }
+            Message::ShareBurned {
+                market_id,
+                outcome_id,
+                amount,
+            } => {
+                // Runs on User Chain (Receipt)
+                let market_chain_id = self.runtime.application_creator_chain_id();
+                assert_eq!(
+                    self.runtime.message_origin_chain_id(),
+                    Some(market_chain_id),
+                    "Fake receipt detected"
+                );
+
+                let key = (market_id, outcome_id);
+                let current_shares = self.state.my_shares.get(&key).await.expect("View error").unwrap_or(0);
+                self.set_my_shares(market_id, outcome_id, current_shares.saturating_sub(amount));
+            }
+            Message::Refund {
+                owner,
+                token,
+                value,
+                return_chain_id,
+            } => {
+                // Runs on Market Chain
+                assert_eq!(
+                    self.runtime.chain_id(),
+                    self.runtime.application_creator_chain_id(),
+                    "Message only valid on market creator chain"
+                );
+
+                let token_app_id = token.with_abi::<my_fungible::MyFungibleAbi>();
+                let target_account = FungibleAccount {
+                    chain_id: return_chain_id,
+                    owner,
+                };
+                self.send_tokens_to(token_app_id, target_account, value);
+            }
+        }
+    }
+
+    async fn process_streams(&mut self, updates: Vec<StreamUpdate>) {
+        // Runs on User Chain: new events published by the market chain
+        for update in updates {
+            for index in update.new_indices() {
+                let event = self.runtime.read_event(update.chain_id, update.stream_id.stream_name.clone(), index);
+                self.record_update(&event);
             }
         }
     }
@@ -195,13 +493,83 @@ impl TruemarketContract {
             owner: buyer,
             value,
             return_chain_id,
+            token,
         };
+        self.send_tracked(message, market_chain_id);
+    }
+
+    /// Sends an authenticated message that bounces back here if the market chain rejects it.
+    fn send_tracked(&mut self, message: Message, market_chain_id: ChainId) {
         self.runtime
             .prepare_message(message)
             .with_authentication()
+            .with_tracking()
             .send_to(market_chain_id);
     }
 
+    async fn handle_bounce(&mut self, message: Message) {
+        match message {
+            Message::Buy {
+                market_id,
+                outcome_id,
+                owner,
+                value,
+                return_chain_id,
+                token,
+                ..
+            } => {
+                // The tokens already sit in our account on the market chain; ask for them back
+                let market_chain_id = self.runtime.application_creator_chain_id();
+                let refund = Message::Refund {
+                    owner,
+                    token,
+                    value,
+                    return_chain_id,
+                };
+                self.runtime
+                    .prepare_message(refund)
+                    .with_authentication()
+                    .send_to(market_chain_id);
+
+                self.record_failure(TradeAction::Buy, market_id, Some(outcome_id), owner, value);
+            }
+            // Nothing was moved yet, so there is nothing to give back
+            Message::Sell {
+                market_id,
+                outcome_id,
+                value,
+                owner,
+                ..
+            } => {
+                self.record_failure(TradeAction::Sell, market_id, Some(outcome_id), owner, value);
+            }
+            Message::Claim { market_id, owner, .. } => {
+                self.record_failure(TradeAction::Claim, market_id, None, owner, Amount::ZERO);
+            }
+            // Receipts and refunds are not tracked, so they never bounce
+            Message::ShareMinted { .. } | Message::ShareBurned { .. } | Message::Refund { .. } => {}
+        }
+    }
+
+    fn record_failure(
+        &mut self,
+        action: TradeAction,
+        market_id: u64,
+        outcome_id: Option<u32>,
+        owner: AccountOwner,
+        value: Amount,
+    ) {
+        let timestamp = self.runtime.system_time();
+        self.state.failed_trades.push(TradeFailure {
+            action,
+            market_id,
+            outcome_id,
+            owner,
+            value,
+            timestamp,
+        });
+    }
+
     async fn buy(
         &mut self,
         market_id: u64,
@@ -210,42 +578,35 @@ impl TruemarketContract {
         buyer: AccountOwner,
         value: Amount,
         recipient_chain_id: ChainId,
-    ) {
+    ) -> TradeResult {
         let mut market = self.state.markets.get(&market_id).await
             .expect("State error").expect("Market not found");
-            
-        let token_app_id = market.token.with_abi::<my_fungible::MyFungibleAbi>();
 
         // 1. HANDLE FUNDS
         // If this is a local operation (no message origin), we need to pull funds.
         // If this is a remote message, funds were PUSHED in buy_remote, so we skip this.
         if self.runtime.message_origin_chain_id().is_none() {
-            self.receive_tokens(token_app_id, buyer, value);
+            self.receive_collateral(&market, buyer, value).await;
         }
 
         // 2. LOGIC
         assert_eq!(market.state, MarketState::Open, "Market not open");
+        self.runtime.assert_before(market.closes_at_timestamp);
         assert!(!market.paused, "Market paused");
         assert!(outcome_id < market.outcome_count, "Invalid outcome");
+        // LP fees would have no one to accrue to
+        assert!(market.liquidity > 0, "Market has no liquidity");
 
         let value_units = Self::amount_to_units(value);
         let min_shares_units = Self::amount_to_units(min_outcome_shares_to_buy);
 
-        let shares_bought = self.calc_buy_amount(&market, value_units, outcome_id);
+        let quote = amm::quote_buy(&market.market_maker, &Self::pools(&market), &market.buy_fees, outcome_id, value_units)
+            .expect("Unable to price trade");
+        let shares_bought = quote.shares;
         assert!(shares_bought >= min_shares_units, "Slippage: not enough shares");
 
-        // Fees
-        let fee_amount = (value_units * market.buy_fees.fee as u128) / FEE_DENOMINATOR;
-        let treasury_fee = (value_units * market.buy_fees.treasury_fee as u128) / FEE_DENOMINATOR;
-        let distributor_fee = (value_units * market.buy_fees.distributor_fee as u128) / FEE_DENOMINATOR;
-
-        let value_minus_fees = value_units
-            .checked_sub(fee_amount).expect("U")
-            .checked_sub(treasury_fee).expect("U")
-            .checked_sub(distributor_fee).expect("Fee underflow");
-
-        market.fee_accumulator += fee_amount;
-        self.add_shares_to_market(&mut market, value_minus_fees);
+        Self::accrue_fees(&mut market, quote.fee);
+        self.add_shares_to_market(&mut market, quote.pool_amount);
 
         let outcome = &mut market.outcomes[outcome_id as usize];
         outcome.shares_available -= shares_bought;
@@ -257,154 +618,1225 @@ impl TruemarketContract {
         self.state.market_shares.insert(&key, user_shares + shares_bought).expect("Save");
 
         // Fee Payouts
-        if treasury_fee > 0 {
-            self.send_tokens(token_app_id, market.treasury, Self::units_to_amount(treasury_fee));
-        }
-        if distributor_fee > 0 {
-            self.send_tokens(token_app_id, market.distributor, Self::units_to_amount(distributor_fee));
-        }
+        self.send_collateral(&market, market.treasury, Self::units_to_amount(quote.treasury_fee)).await;
+        self.send_collateral(&market, market.distributor, Self::units_to_amount(quote.distributor_fee)).await;
 
+        let prices = self.calc_prices(&market);
+        let trade = Self::trade_result(market_id, outcome_id, value, &quote, prices);
+        self.record_trade(&mut market, TradeAction::Buy, buyer, &trade);
+        let label = market.outcomes[outcome_id as usize].label.clone();
         self.state.markets.insert(&market_id, market).expect("Save market");
 
         // 3. SEND RECEIPT
-        let current_chain = self.runtime.chain_id();
-        if recipient_chain_id == current_chain {
-            let local_key = (market_id, outcome_id);
-            let current = self.state.my_shares.get(&local_key).await.expect("E").unwrap_or(0);
-            self.state.my_shares.insert(&local_key, current + shares_bought).expect("Save local");
-        } else {
-            let msg = Message::ShareMinted {
-                market_id,
-                outcome_id,
-                amount: shares_bought,
-            };
-            self.runtime
-                .prepare_message(msg)
-                .with_authentication()
-                .send_to(recipient_chain_id);
-        }
+        self.mint_receipt(recipient_chain_id, market_id, outcome_id, shares_bought, label).await;
+
+        trade
     }
 
-    // ----- Helpers (Same as before) -----
+    async fn sell(
+        &mut self,
+        market_id: u64,
+        outcome_id: u32,
+        value: Amount,
+        max_outcome_shares_to_sell: Amount,
+        seller: AccountOwner,
+        recipient_chain_id: ChainId,
+    ) -> TradeResult {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
 
-    fn amount_to_units(amount: Amount) -> u128 { u128::from(amount) }
-    fn units_to_amount(units: u128) -> Amount { Amount::from_attos(units) }
+        // 1. LOGIC
+        assert_eq!(market.state, MarketState::Open, "Market not open");
+        self.runtime.assert_before(market.closes_at_timestamp);
+        assert!(!market.paused, "Market paused");
+        assert!(outcome_id < market.outcome_count, "Invalid outcome");
+        assert!(!value.is_zero(), "Nothing to sell");
+        assert!(market.liquidity > 0, "Market has no liquidity");
 
-    fn validate_fees(fees: &Fees) {
-        assert!(fees.fee <= MAX_FEE);
-        assert!(fees.treasury_fee <= MAX_FEE);
-        assert!(fees.distributor_fee <= MAX_FEE);
-    }
+        let value_units = Self::amount_to_units(value);
+        let max_shares_units = Self::amount_to_units(max_outcome_shares_to_sell);
 
-    fn receive_tokens(&mut self, token: ApplicationId<my_fungible::MyFungibleAbi>, from: AccountOwner, amount: Amount) {
-        let app_owner: AccountOwner = self.runtime.application_id().into();
-        let target_account = FungibleAccount {
-            chain_id: self.runtime.chain_id(),
-            owner: app_owner,
-        };
-        let transfer = my_fungible::Operation::Transfer {
-            owner: from,
-            amount,
-            target_account,
+        let quote = amm::quote_sell(&market.market_maker, &Self::pools(&market), &market.sell_fees, outcome_id, value_units)
+            .expect("Insufficient liquidity");
+        let shares_sold = quote.shares;
+        assert!(shares_sold <= max_shares_units, "Slippage: too many shares");
+
+        // Global Ledger Update
+        let key = (market_id, outcome_id, seller);
+        let user_shares = self.state.market_shares.get(&key).await.expect("E").unwrap_or(0);
+        assert!(user_shares >= shares_sold, "Insufficient shares");
+        self.state.market_shares.insert(&key, user_shares - shares_sold).expect("Save");
+
+        // Shares go back into the pool, then the payout is taken out of every pool
+        let outcome = &mut market.outcomes[outcome_id as usize];
+        outcome.shares_available += shares_sold;
+        market.shares_available += shares_sold;
+        self.remove_shares_from_market(&mut market, quote.pool_amount);
+
+        Self::accrue_fees(&mut market, quote.fee);
+
+        // 2. PAYOUTS
+        let seller_account = FungibleAccount {
+            chain_id: recipient_chain_id,
+            owner: seller,
         };
-        self.runtime.call_application(true, token, &transfer);
+        self.send_collateral_to(&market, seller_account, value).await;
+        self.send_collateral(&market, market.treasury, Self::units_to_amount(quote.treasury_fee)).await;
+        self.send_collateral(&market, market.distributor, Self::units_to_amount(quote.distributor_fee)).await;
+
+        let prices = self.calc_prices(&market);
+        let trade = Self::trade_result(market_id, outcome_id, value, &quote, prices);
+        self.record_trade(&mut market, TradeAction::Sell, seller, &trade);
+        self.state.markets.insert(&market_id, market).expect("Save market");
+
+        // 3. UPDATE RECEIPT
+        self.burn_receipt(recipient_chain_id, market_id, outcome_id, shares_sold).await;
+
+        trade
     }
 
-    fn send_tokens(&mut self, token: ApplicationId<my_fungible::MyFungibleAbi>, to: AccountOwner, amount: Amount) {
-        if amount.is_zero() { return; }
-        let target_account = FungibleAccount {
-            chain_id: self.runtime.chain_id(),
-            owner: to,
-        };
-        let app_owner: AccountOwner = self.runtime.application_id().into();
-        let transfer = my_fungible::Operation::Transfer {
-            owner: app_owner,
-            amount,
-            target_account,
-        };
-        self.runtime.call_application(true, token, &transfer);
+    async fn close_market(&mut self, market_id: u64) {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        assert_eq!(market.state, MarketState::Open, "Market not open");
+        assert!(
+            self.runtime.system_time() >= market.closes_at_timestamp,
+            "Market has not reached its closing time"
+        );
+
+        self.set_market_state(&mut market, MarketState::Closed).await;
+        self.state.markets.insert(&market_id, market).expect("Save market");
     }
 
-    async fn create_market(
-        &mut self,
-        value: Amount,
-        closes_at: Timestamp,
-        outcomes: u32,
-        token: ApplicationId,
-        distribution: Vec<u64>,
-        question: String,
-        image: String,
-        arbitrator: AccountOwner,
-        buy_fees: Fees,
-        sell_fees: Fees,
-        treasury: AccountOwner,
-        distributor: AccountOwner,
-        realitio_timeout: u32,
-        manager: AccountOwner,
-    ) {
-        let _ = distribution;
-        let creator = self.runtime.authenticated_signer().expect("Auth required");
+    async fn resolve_market(&mut self, market_id: u64, outcome_id: u32, caller: AccountOwner) {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
 
-        assert!(!value.is_zero());
-        self.runtime.assert_before(closes_at);
-        assert!(outcomes >= 2 && outcomes <= MAX_OUTCOMES);
-        Self::validate_fees(&buy_fees);
-        Self::validate_fees(&sell_fees);
+        assert_eq!(caller, market.arbitrator, "Only the arbitrator can resolve");
+        assert_eq!(market.state, MarketState::Closed, "Market not closed");
+        assert!(market.scalar_range.is_none(), "Scalar markets resolve to a value");
+        assert!(market.event_id.is_none(), "Grouped markets resolve through their event");
+        assert!(outcome_id < market.outcome_count, "Invalid outcome");
 
-        let fungible_id = token.with_abi::<my_fungible::MyFungibleAbi>();
-        self.receive_tokens(fungible_id, creator, value);
+        if let Some(question) = self.arbitrated_question(market_id).await {
+            self.settle_bonds(&market, &question, Some(outcome_id));
+        }
 
-        let market_id = *self.state.market_index.get();
-        let question_id = format!("q_{}_{}", market_id, question);
+        self.set_market_state(&mut market, MarketState::Resolved).await;
+        market.resolved_outcome_id = Some(outcome_id);
+        self.state.markets.insert(&market_id, market).expect("Save market");
+        self.emit(TruemarketEvent::Resolved { market_id, outcome_id: Some(outcome_id), scalar_value: None });
+    }
 
-        let mut market = Market {
-            id: market_id,
-            closes_at_timestamp: closes_at,
-            balance: Amount::ZERO,
+    async fn resolve_scalar_market(&mut self, market_id: u64, value: i64, caller: AccountOwner) {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        assert_eq!(caller, market.arbitrator, "Only the arbitrator can resolve");
+        assert_eq!(market.state, MarketState::Closed, "Market not closed");
+        let range = market.scalar_range.expect("Not a scalar market");
+
+        // Short and long shares then redeem like a voided market's, at fixed prices
+        market.payout_prices = range.payout_prices(value);
+        market.resolved_value = Some(value);
+        self.set_market_state(&mut market, MarketState::Resolved).await;
+        self.state.markets.insert(&market_id, market).expect("Save market");
+        self.emit(TruemarketEvent::Resolved { market_id, outcome_id: None, scalar_value: Some(value) });
+    }
+
+    async fn claim_winnings(&mut self, market_id: u64, owner: AccountOwner, recipient_chain_id: ChainId) -> Amount {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        assert_eq!(market.state, MarketState::Resolved, "Market not resolved");
+        if market.scalar_range.is_some() {
+            return self.claim_payouts(market_id, owner, recipient_chain_id).await;
+        }
+        let claim_key = (market_id, owner);
+        let claimed = self.state.claimed.get(&claim_key).await.expect("E").unwrap_or(false);
+        assert!(!claimed, "Winnings already claimed");
+
+        let outcome_id = market.resolved_outcome_id.expect("Resolved market without outcome");
+        let key = (market_id, outcome_id, owner);
+        let shares = self.state.market_shares.get(&key).await.expect("E").unwrap_or(0);
+        assert!(shares > 0, "No winning shares");
+
+        let payout = Self::redeem_shares(&mut market, outcome_id, shares);
+        self.state.market_shares.remove(&key).expect("Save");
+        self.state.claimed.insert(&claim_key, true).expect("Save");
+
+        let owner_account = FungibleAccount {
+            chain_id: recipient_chain_id,
+            owner,
+        };
+        self.send_collateral_to(&market, owner_account, Self::units_to_amount(payout)).await;
+        self.state.markets.insert(&market_id, market).expect("Save market");
+
+        self.burn_receipt(recipient_chain_id, market_id, outcome_id, shares).await;
+
+        let amount = Self::units_to_amount(payout);
+        self.emit(TruemarketEvent::Claimed { market_id, owner, amount });
+        amount
+    }
+
+    async fn void_market(&mut self, market_id: u64, equal_split: bool, caller: AccountOwner) {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        assert_eq!(caller, market.arbitrator, "Only the arbitrator can resolve");
+        assert_eq!(market.state, MarketState::Closed, "Market not closed");
+        // Conversions may have moved collateral between the event's markets
+        assert!(market.event_id.is_none(), "Grouped markets resolve through their event");
+
+        if let Some(question) = self.arbitrated_question(market_id).await {
+            self.settle_bonds(&market, &question, None);
+        }
+
+        // Freeze the payout prices so later withdrawals can't move them
+        market.payout_prices = if equal_split {
+            vec![ONE / market.outcome_count as u128; market.outcome_count as usize]
+        } else {
+            self.calc_prices(&market)
+        };
+        self.set_market_state(&mut market, MarketState::Voided).await;
+        self.state.markets.insert(&market_id, market).expect("Save market");
+        self.emit(TruemarketEvent::Resolved { market_id, outcome_id: None, scalar_value: None });
+    }
+
+    /// Burns the owner's shares of every outcome at the market's frozen payout prices. Used by
+    /// voided markets and resolved scalar markets.
+    async fn claim_payouts(&mut self, market_id: u64, owner: AccountOwner, recipient_chain_id: ChainId) -> Amount {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        let scalar_resolved = market.state == MarketState::Resolved && market.scalar_range.is_some();
+        assert!(market.state == MarketState::Voided || scalar_resolved, "Market not voided");
+        let claim_key = (market_id, owner);
+        let claimed = self.state.claimed.get(&claim_key).await.expect("E").unwrap_or(false);
+        assert!(!claimed, "Shares already claimed");
+
+        let mut payout = 0u128;
+        for outcome_id in 0..market.outcome_count {
+            let key = (market_id, outcome_id, owner);
+            let shares = self.state.market_shares.get(&key).await.expect("E").unwrap_or(0);
+            if shares == 0 {
+                continue;
+            }
+
+            payout += Self::redeem_shares(&mut market, outcome_id, shares);
+            self.state.market_shares.remove(&key).expect("Save");
+            self.burn_receipt(recipient_chain_id, market_id, outcome_id, shares).await;
+        }
+        assert!(payout > 0, "No shares to claim");
+        self.state.claimed.insert(&claim_key, true).expect("Save");
+
+        let owner_account = FungibleAccount {
+            chain_id: recipient_chain_id,
+            owner,
+        };
+        self.send_collateral_to(&market, owner_account, Self::units_to_amount(payout)).await;
+        self.state.markets.insert(&market_id, market).expect("Save market");
+
+        let amount = Self::units_to_amount(payout);
+        self.emit(TruemarketEvent::Claimed { market_id, owner, amount });
+        amount
+    }
+
+    async fn add_liquidity(&mut self, market_id: u64, value: Amount, provider: AccountOwner) -> Amount {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        assert_eq!(market.state, MarketState::Open, "Market not open");
+        self.runtime.assert_before(market.closes_at_timestamp);
+        assert!(!market.paused, "Market paused");
+        assert!(!value.is_zero(), "Nothing to add");
+        // Deeper pools would change LMSR prices; its depth is set by `b` alone
+        assert_eq!(market.market_maker, MarketMaker::Fpmm, "LMSR liquidity is fixed at creation");
+
+        self.receive_collateral(&market, provider, value).await;
+        self.settle_fees(&market, provider).await;
+
+        let value_units = Self::amount_to_units(value);
+        let (liquidity_amount, send_back) = liquidity::add_amounts(&Self::pools(&market), market.liquidity, value_units)
+            .expect("Overflow");
+
+        self.add_shares_to_market(&mut market, value_units);
+        for (outcome_id, amount) in send_back.into_iter().enumerate() {
+            if amount > 0 {
+                market.outcomes[outcome_id].shares_available -= amount;
+                market.shares_available -= amount;
+                self.credit_shares(market_id, outcome_id as u32, provider, amount).await;
+            }
+        }
+
+        market.liquidity += liquidity_amount;
+        let key = (market_id, provider);
+        let current = self.state.liquidity_shares.get(&key).await.expect("E").unwrap_or(0);
+        self.state.liquidity_shares.insert(&key, current + liquidity_amount).expect("Save");
+        self.update_fee_debt(&market, provider, current + liquidity_amount);
+
+        let liquidity = Self::units_to_amount(market.liquidity);
+        self.state.markets.insert(&market_id, market).expect("Save market");
+
+        let shares = Self::units_to_amount(liquidity_amount);
+        self.emit(TruemarketEvent::LiquidityChanged { market_id, provider, added: true, shares, liquidity });
+        shares
+    }
+
+    async fn remove_liquidity(&mut self, market_id: u64, shares: Amount, provider: AccountOwner) -> Amount {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        let shares_units = Self::amount_to_units(shares);
+        assert!(shares_units > 0, "Nothing to remove");
+        assert!(
+            market.market_maker == MarketMaker::Fpmm || market.state != MarketState::Open,
+            "LMSR liquidity is locked until trading ends"
+        );
+
+        let key = (market_id, provider);
+        let current = self.state.liquidity_shares.get(&key).await.expect("E").unwrap_or(0);
+        assert!(current >= shares_units, "Insufficient liquidity shares");
+        self.settle_fees(&market, provider).await;
+
+        let pools = Self::pools(&market);
+        let payout = match market.state {
+            MarketState::Open | MarketState::Closed => {
+                // Complete sets are burned for collateral; whatever is left over on the
+                // larger pools is handed out as outcome shares.
+                let (liquidity_amount, send_back) = liquidity::remove_amounts(&pools, market.liquidity, shares_units)
+                    .expect("Overflow");
+                for (outcome_id, send_back) in send_back.into_iter().enumerate() {
+                    if send_back > 0 {
+                        market.outcomes[outcome_id].shares_available -= send_back;
+                        market.shares_available -= send_back;
+                        self.credit_shares(market_id, outcome_id as u32, provider, send_back).await;
+                    }
+                }
+                self.remove_shares_from_market(&mut market, liquidity_amount);
+                liquidity_amount
+            }
+            MarketState::Resolved | MarketState::Voided => {
+                // Trading is over, so the provider's cut of every pool is redeemed directly
+                let outcome_shares = liquidity::pool_shares(&pools, market.liquidity, shares_units)
+                    .expect("Overflow");
+                let mut payout = 0u128;
+                for (outcome_id, amount) in outcome_shares.iter().copied().enumerate() {
+                    market.outcomes[outcome_id].shares_available -= amount;
+                    market.shares_available -= amount;
+                    payout += Self::redeem_shares(&mut market, outcome_id as u32, amount);
+                }
+                payout
+            }
+        };
+
+        market.liquidity -= shares_units;
+        self.state.liquidity_shares.insert(&key, current - shares_units).expect("Save");
+        self.update_fee_debt(&market, provider, current - shares_units);
+
+        self.send_collateral(&market, provider, Self::units_to_amount(payout)).await;
+        let liquidity = Self::units_to_amount(market.liquidity);
+        self.state.markets.insert(&market_id, market).expect("Save market");
+
+        self.emit(TruemarketEvent::LiquidityChanged { market_id, provider, added: false, shares, liquidity });
+        Self::units_to_amount(payout)
+    }
+
+    async fn claim_fees(&mut self, market_id: u64, provider: AccountOwner) -> Amount {
+        let market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        let paid = self.settle_fees(&market, provider).await;
+        assert!(paid > 0, "No fees to claim");
+        Self::units_to_amount(paid)
+    }
+
+    async fn split_position(&mut self, market_id: u64, value: Amount, owner: AccountOwner) {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        assert_eq!(market.state, MarketState::Open, "Market not open");
+        assert!(!market.paused, "Market paused");
+        assert!(!value.is_zero(), "Nothing to split");
+
+        self.receive_collateral(&market, owner, value).await;
+
+        // Every complete set is backed by exactly one unit of collateral
+        let amount = Self::amount_to_units(value);
+        market.balance = market.balance.try_add(value).expect("Over");
+        for outcome_id in 0..market.outcome_count {
+            market.outcomes[outcome_id as usize].shares_total += amount;
+            self.credit_shares(market_id, outcome_id, owner, amount).await;
+        }
+
+        self.state.markets.insert(&market_id, market).expect("Save market");
+    }
+
+    async fn merge_positions(&mut self, market_id: u64, shares: Amount, owner: AccountOwner) {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        // Once resolved, shares are redeemed through the claims instead
+        assert!(
+            matches!(market.state, MarketState::Open | MarketState::Closed),
+            "Market already resolved"
+        );
+        let amount = Self::amount_to_units(shares);
+        assert!(amount > 0, "Nothing to merge");
+
+        let current_chain = self.runtime.chain_id();
+        for outcome_id in 0..market.outcome_count {
+            let key = (market_id, outcome_id, owner);
+            let owned = self.state.market_shares.get(&key).await.expect("E").unwrap_or(0);
+            assert!(owned >= amount, "Insufficient shares");
+            self.state.market_shares.insert(&key, owned - amount).expect("Save");
+            market.outcomes[outcome_id as usize].shares_total -= amount;
+            self.burn_receipt(current_chain, market_id, outcome_id, amount).await;
+        }
+
+        market.balance = market.balance.try_sub(shares).expect("Under");
+        self.send_collateral(&market, owner, shares).await;
+        self.state.markets.insert(&market_id, market).expect("Save market");
+    }
+
+    async fn set_paused(&mut self, market_id: u64, paused: bool, caller: AccountOwner) {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        assert_eq!(caller, market.manager, "Only the manager can update the market");
+        assert_ne!(market.paused, paused, "Market already in requested pause state");
+
+        let action = if paused { MarketAction::Paused } else { MarketAction::Unpaused };
+        self.record_audit(market_id, action, caller, &market.paused, &paused);
+        market.paused = paused;
+        self.state.markets.insert(&market_id, market).expect("Save market");
+        self.emit(TruemarketEvent::Paused { market_id, paused });
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn update_market(&mut self, market_id: u64, params: UpdateMarketParams, caller: AccountOwner) {
+        let UpdateMarketParams { image, treasury, distributor, buy_fees, sell_fees } = params;
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        assert_eq!(caller, market.manager, "Only the manager can update the market");
+
+        if let Some(image) = image {
+            self.record_audit(market_id, MarketAction::ImageUpdated, caller, &market.image, &image);
+            market.image = image;
+        }
+        if let Some(treasury) = treasury {
+            self.record_audit(market_id, MarketAction::TreasuryUpdated, caller, &market.treasury, &treasury);
+            market.treasury = treasury;
+        }
+        if let Some(distributor) = distributor {
+            self.record_audit(market_id, MarketAction::DistributorUpdated, caller, &market.distributor, &distributor);
+            market.distributor = distributor;
+        }
+        if let Some(buy_fees) = buy_fees {
+            Self::validate_fees(&buy_fees);
+            self.record_audit(market_id, MarketAction::BuyFeesUpdated, caller, &market.buy_fees, &buy_fees);
+            market.buy_fees = buy_fees;
+        }
+        if let Some(sell_fees) = sell_fees {
+            Self::validate_fees(&sell_fees);
+            self.record_audit(market_id, MarketAction::SellFeesUpdated, caller, &market.sell_fees, &sell_fees);
+            market.sell_fees = sell_fees;
+        }
+
+        self.state.markets.insert(&market_id, market).expect("Save market");
+    }
+
+    async fn submit_answer(&mut self, market_id: u64, outcome_id: u32, bond: Amount, answerer: AccountOwner) {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        let token_app_id = market.token.with_abi::<my_fungible::MyFungibleAbi>();
+        let now = self.runtime.system_time();
+
+        // Answering can start as soon as trading has ended
+        if market.state == MarketState::Open {
+            assert!(now >= market.closes_at_timestamp, "Market has not reached its closing time");
+            self.set_market_state(&mut market, MarketState::Closed).await;
+        }
+        assert_eq!(market.state, MarketState::Closed, "Market not closed");
+        assert!(market.scalar_range.is_none(), "Scalar markets are resolved by their arbitrator");
+        assert!(market.event_id.is_none(), "Grouped markets resolve through their event");
+        assert!(outcome_id < market.outcome_count, "Invalid outcome");
+        assert!(!bond.is_zero(), "Bond required");
+
+        let mut question = self.state.questions.get(&market_id).await.expect("E")
+            .unwrap_or(Question {
+                answers: Vec::new(),
+                finalizes_at: now,
+                arbitration_requested_by: None,
+                arbitration_fee: Amount::ZERO,
+            });
+        assert!(question.arbitration_requested_by.is_none(), "Arbitration pending");
+        if let Some(best) = question.answers.last() {
+            assert!(now < question.finalizes_at, "Answer already final");
+            assert!(
+                Self::amount_to_units(bond) >= Self::amount_to_units(best.bond) * 2,
+                "Bond must at least double the previous one"
+            );
+        }
+
+        self.receive_tokens(token_app_id, answerer, bond);
+
+        question.answers.push(Answer { answerer, outcome_id, bond });
+        question.finalizes_at = now.saturating_add(TimeDelta::from_secs(market.realitio_timeout as u64));
+        self.state.questions.insert(&market_id, question).expect("Save");
+        self.state.markets.insert(&market_id, market).expect("Save market");
+    }
+
+    async fn finalize_answer(&mut self, market_id: u64) {
+        let mut market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        assert_eq!(market.state, MarketState::Closed, "Market not closed");
+        let question = self.state.questions.get(&market_id).await.expect("E")
+            .expect("No answers");
+        let outcome_id = question.answers.last().expect("No answers").outcome_id;
+        assert!(question.arbitration_requested_by.is_none(), "Arbitration pending");
+        assert!(self.runtime.system_time() >= question.finalizes_at, "Answer not final yet");
+
+        self.settle_bonds(&market, &question, Some(outcome_id));
+
+        self.set_market_state(&mut market, MarketState::Resolved).await;
+        market.resolved_outcome_id = Some(outcome_id);
+        self.state.markets.insert(&market_id, market).expect("Save market");
+        self.emit(TruemarketEvent::Resolved { market_id, outcome_id: Some(outcome_id), scalar_value: None });
+    }
+
+    async fn request_arbitration(&mut self, market_id: u64, fee: Amount, requester: AccountOwner) {
+        let market = self.state.markets.get(&market_id).await
+            .expect("State error").expect("Market not found");
+
+        assert_eq!(market.state, MarketState::Closed, "Market not closed");
+        let mut question = self.state.questions.get(&market_id).await.expect("E")
+            .expect("No answers");
+        assert!(question.arbitration_requested_by.is_none(), "Arbitration pending");
+        assert!(self.runtime.system_time() < question.finalizes_at, "Answer already final");
+        // Escalating must cost as much as the answer it challenges
+        let best_bond = question.answers.last().expect("No answers").bond;
+        assert!(fee >= best_bond, "Arbitration fee must cover the best bond");
+
+        self.receive_tokens(market.token.with_abi::<my_fungible::MyFungibleAbi>(), requester, fee);
+        question.arbitration_requested_by = Some(requester);
+        question.arbitration_fee = fee;
+        self.state.questions.insert(&market_id, question).expect("Save");
+    }
+
+    async fn create_event(&mut self, title: String, closes_at: Timestamp, market_ids: Vec<u64>, manager: AccountOwner) -> u64 {
+        assert!(!title.trim().is_empty(), "Event title required");
+        assert!((2..=MAX_OUTCOMES as usize).contains(&market_ids.len()), "Invalid number of markets");
+        assert!(
+            market_ids.iter().enumerate().all(|(i, id)| !market_ids[..i].contains(id)),
+            "Duplicate market"
+        );
+
+        let event_id = *self.state.event_index.get();
+        let mut shared = None;
+        for market_id in &market_ids {
+            let mut market = self.state.markets.get(market_id).await
+                .expect("State error").expect("Market not found");
+
+            assert_eq!(manager, market.manager, "Only the manager can group a market");
+            assert_eq!(market.state, MarketState::Open, "Market not open");
+            assert!(market.event_id.is_none(), "Market already grouped");
+            assert!(
+                market.outcome_count == 2 && market.scalar_range.is_none() && market.parent.is_none(),
+                "Only plain yes/no markets can be grouped"
+            );
+            assert_eq!(market.closes_at_timestamp, closes_at, "Grouped markets must share the closing time");
+            // Collateral moves between the markets, so it must be the same everywhere
+            let (token, arbitrator) = *shared.get_or_insert((market.token, market.arbitrator));
+            assert_eq!(market.token, token, "Grouped markets must share the token");
+            assert_eq!(market.arbitrator, arbitrator, "Grouped markets must share the arbitrator");
+
+            market.event_id = Some(event_id);
+            self.state.markets.insert(market_id, market).expect("Save market");
+        }
+
+        let (token, arbitrator) = shared.expect("Event without markets");
+        let event = Event {
+            id: event_id,
+            title,
+            closes_at,
+            market_ids,
+            token,
+            arbitrator,
+            manager,
+            resolved_market_id: None,
+        };
+        self.state.events.insert(&event_id, event).expect("Save event");
+        self.state.event_index.set(event_id + 1);
+        event_id
+    }
+
+    async fn resolve_event(&mut self, event_id: u64, winner: u64, caller: AccountOwner) {
+        let mut event = self.state.events.get(&event_id).await
+            .expect("State error").expect("Event not found");
+
+        assert_eq!(caller, event.arbitrator, "Only the arbitrator can resolve");
+        assert!(event.resolved_market_id.is_none(), "Event already resolved");
+        assert!(event.market_ids.contains(&winner), "Market not in event");
+        assert!(self.runtime.system_time() >= event.closes_at, "Event has not reached its closing time");
+
+        let mut markets = Vec::with_capacity(event.market_ids.len());
+        for market_id in &event.market_ids {
+            let market = self.state.markets.get(market_id).await
+                .expect("State error").expect("Market not found");
+            markets.push(market);
+        }
+
+        // Conversions leave some markets with more shares than collateral and others with less;
+        // with the winner known, each market gets exactly what its winning side redeems for.
+        let balances: Vec<u128> = markets.iter().map(|market| Self::amount_to_units(market.balance)).collect();
+        let no_totals: Vec<u128> = markets
+            .iter()
+            .map(|market| market.outcomes[OUTCOME_NO as usize].shares_total)
+            .collect();
+        let winner_index = event.market_ids.iter().position(|id| *id == winner).expect("Market not in event");
+        let balances = neg_risk::settle_balances(&balances, &no_totals, winner_index).expect("Under");
+
+        for (mut market, balance) in markets.into_iter().zip(balances) {
+            market.balance = Self::units_to_amount(balance);
+            let outcome_id = if market.id == winner { OUTCOME_YES } else { OUTCOME_NO };
+            let market_id = market.id;
+            self.set_market_state(&mut market, MarketState::Resolved).await;
+            market.resolved_outcome_id = Some(outcome_id);
+            self.state.markets.insert(&market_id, market).expect("Save market");
+            self.emit(TruemarketEvent::Resolved { market_id, outcome_id: Some(outcome_id), scalar_value: None });
+        }
+
+        event.resolved_market_id = Some(winner);
+        self.state.events.insert(&event_id, event).expect("Save event");
+    }
+
+    async fn convert_positions(&mut self, event_id: u64, market_ids: Vec<u64>, shares: Amount, owner: AccountOwner) -> Amount {
+        let event = self.state.events.get(&event_id).await
+            .expect("State error").expect("Event not found");
+
+        assert!(event.resolved_market_id.is_none(), "Event already resolved");
+        assert!(!market_ids.is_empty(), "No positions to convert");
+        assert!(market_ids.iter().all(|id| event.market_ids.contains(id)), "Market not in event");
+        assert!(
+            market_ids.iter().enumerate().all(|(i, id)| !market_ids[..i].contains(id)),
+            "Duplicate market"
+        );
+        let amount = Self::amount_to_units(shares);
+        assert!(amount > 0, "Nothing to convert");
+
+        // Only one market can resolve to yes, so all but one of the burned no shares
+        // are certain to pay out: that part is released right away.
+        let converted: Vec<bool> = event.market_ids.iter().map(|id| market_ids.contains(id)).collect();
+        let debits = neg_risk::conversion_debits(&converted, amount);
+        let released: u128 = debits.iter().sum();
+
+        let current_chain = self.runtime.chain_id();
+        for (market_id, debit) in event.market_ids.iter().zip(debits) {
+            let mut market = self.state.markets.get(market_id).await
+                .expect("State error").expect("Market not found");
+
+            if market_ids.contains(market_id) {
+                let key = (*market_id, OUTCOME_NO, owner);
+                let owned = self.state.market_shares.get(&key).await.expect("E").unwrap_or(0);
+                assert!(owned >= amount, "Insufficient shares");
+                self.state.market_shares.insert(&key, owned - amount).expect("Save");
+                market.outcomes[OUTCOME_NO as usize].shares_total -= amount;
+                self.burn_receipt(current_chain, *market_id, OUTCOME_NO, amount).await;
+
+                market.balance = market.balance.try_sub(Self::units_to_amount(debit)).expect("Under");
+            } else {
+                // Backed by the collateral left behind by the burned no shares until the
+                // event resolves and rebalances the markets
+                market.outcomes[OUTCOME_YES as usize].shares_total += amount;
+                self.credit_shares(*market_id, OUTCOME_YES, owner, amount).await;
+            }
+            self.state.markets.insert(market_id, market).expect("Save market");
+        }
+
+        let value = Self::units_to_amount(released);
+        if released > 0 {
+            self.send_tokens(event.token.with_abi::<my_fungible::MyFungibleAbi>(), owner, value);
+        }
+        value
+    }
+
+    // ----- Helpers (Same as before) -----
+
+    fn assert_market_chain(&mut self) {
+        assert_eq!(
+            self.runtime.chain_id(),
+            self.runtime.application_creator_chain_id(),
+            "Operation only valid on market creator chain"
+        );
+    }
+
+    fn amount_to_units(amount: Amount) -> u128 { u128::from(amount) }
+    fn units_to_amount(units: u128) -> Amount { Amount::from_attos(units) }
+
+    fn validate_fees(fees: &Fees) {
+        assert!(fees.fee <= MAX_FEE);
+        assert!(fees.treasury_fee <= MAX_FEE);
+        assert!(fees.distributor_fee <= MAX_FEE);
+    }
+
+    /// Loads the market's question for an arbitrator ruling. Once answers have been posted,
+    /// the arbitrator may only rule after someone has requested arbitration.
+    async fn arbitrated_question(&mut self, market_id: u64) -> Option<Question> {
+        let question = self.state.questions.get(&market_id).await.expect("E")?;
+        assert!(
+            question.answers.is_empty() || question.arbitration_requested_by.is_some(),
+            "Question is being answered, arbitration must be requested first"
+        );
+        Some(question)
+    }
+
+    /// Pays out the answer bonds. Correct answerers get their own bond back and the last of
+    /// them also takes every wrong bond. Without a correct answer, the wrong bonds go to
+    /// whoever requested arbitration; a void ruling (`None`) refunds everybody. The
+    /// arbitration fee always goes to the arbitrator.
+    fn settle_bonds(&mut self, market: &Market, question: &Question, ruling: Option<u32>) {
+        let token_app_id = market.token.with_abi::<my_fungible::MyFungibleAbi>();
+        let winner = ruling
+            .and_then(|outcome_id| {
+                question.answers.iter().rev().find(|answer| answer.outcome_id == outcome_id)
+            })
+            .map(|answer| answer.answerer)
+            .or(question.arbitration_requested_by)
+            .unwrap_or(market.arbitrator);
+
+        for answer in &question.answers {
+            let payee = match ruling {
+                Some(outcome_id) if answer.outcome_id != outcome_id => winner,
+                _ => answer.answerer,
+            };
+            self.send_tokens(token_app_id, payee, answer.bond);
+        }
+        if !question.arbitration_fee.is_zero() {
+            self.send_tokens(token_app_id, market.arbitrator, question.arbitration_fee);
+        }
+    }
+
+    fn record_audit<T: serde::Serialize>(
+        &mut self,
+        market_id: u64,
+        action: MarketAction,
+        manager: AccountOwner,
+        previous: &T,
+        value: &T,
+    ) {
+        let timestamp = self.runtime.system_time();
+        self.state.audit_log.push(AuditEntry {
+            market_id,
+            action,
+            manager,
+            timestamp,
+            previous: serde_json::to_string(previous).expect("Serialize"),
+            value: serde_json::to_string(value).expect("Serialize"),
+        });
+    }
+
+    /// Moves a market to `state`, keeping the listing index in sync.
+    async fn set_market_state(&mut self, market: &mut Market, state: MarketState) {
+        self.state.markets_by_state.remove(index_key(state_prefix(market.state), market.id));
+        self.state.markets_by_state.insert(index_key(state_prefix(state), market.id), ());
+        market.state = state;
+    }
+
+    /// Books an LP fee against every outstanding LP share. Trades require `liquidity > 0`.
+    fn accrue_fees(market: &mut Market, fee_amount: u128) {
+        market.fee_accumulator += fee_amount;
+        market.fees_per_share += liquidity::fees_per_share(fee_amount, market.liquidity).expect("Overflow");
+    }
+
+    /// Pays `provider` the LP fees earned since their last settlement and returns the amount.
+    async fn settle_fees(&mut self, market: &Market, provider: AccountOwner) -> u128 {
+        let key = (market.id, provider);
+        let lp_shares = self.state.liquidity_shares.get(&key).await.expect("E").unwrap_or(0);
+        let debt = self.state.fee_debt.get(&key).await.expect("E").unwrap_or(0);
+
+        let earned = liquidity::earned_fees(lp_shares, market.fees_per_share).expect("Overflow");
+        let pending = earned.saturating_sub(debt);
+        self.state.fee_debt.insert(&key, earned).expect("Save");
+
+        self.send_collateral(market, provider, Self::units_to_amount(pending)).await;
+        pending
+    }
+
+    /// Marks every fee earned so far as settled for a provider's new LP balance.
+    fn update_fee_debt(&mut self, market: &Market, provider: AccountOwner, lp_shares: u128) {
+        let debt = liquidity::earned_fees(lp_shares, market.fees_per_share).expect("Overflow");
+        self.state.fee_debt.insert(&(market.id, provider), debt).expect("Save");
+    }
+
+    fn mul_div(a: u128, b: u128, denom: u128) -> u128 {
+        amm::mul_div(a, b, denom).expect("Overflow")
+    }
+
+    /// Adds outcome shares to `owner`'s position, updating the local receipt as well.
+    async fn credit_shares(&mut self, market_id: u64, outcome_id: u32, owner: AccountOwner, amount: u128) {
+        let key = (market_id, outcome_id, owner);
+        let current = self.state.market_shares.get(&key).await.expect("E").unwrap_or(0);
+        self.state.market_shares.insert(&key, current + amount).expect("Save");
+
+        let local_key = (market_id, outcome_id);
+        let current = self.state.my_shares.get(&local_key).await.expect("E").unwrap_or(0);
+        self.set_my_shares(market_id, outcome_id, current + amount);
+    }
+
+    /// Increases the share receipt kept on the owner's chain, locally or through a message.
+    async fn mint_receipt(&mut self, recipient_chain_id: ChainId, market_id: u64, outcome_id: u32, amount: u128, label: String) {
+        if recipient_chain_id == self.runtime.chain_id() {
+            let local_key = (market_id, outcome_id);
+            let current = self.state.my_shares.get(&local_key).await.expect("E").unwrap_or(0);
+            self.set_my_shares(market_id, outcome_id, current + amount);
+        } else {
+            let msg = Message::ShareMinted {
+                market_id,
+                outcome_id,
+                amount,
+                label,
+            };
+            self.runtime
+                .prepare_message(msg)
+                .with_authentication()
+                .send_to(recipient_chain_id);
+        }
+    }
+
+    /// Reduces the share receipt kept on the owner's chain, locally or through a message.
+    async fn burn_receipt(&mut self, recipient_chain_id: ChainId, market_id: u64, outcome_id: u32, amount: u128) {
+        if recipient_chain_id == self.runtime.chain_id() {
+            let local_key = (market_id, outcome_id);
+            let current = self.state.my_shares.get(&local_key).await.expect("E").unwrap_or(0);
+            self.set_my_shares(market_id, outcome_id, current.saturating_sub(amount));
+        } else {
+            let msg = Message::ShareBurned {
+                market_id,
+                outcome_id,
+                amount,
+            };
+            self.runtime
+                .prepare_message(msg)
+                .with_authentication()
+                .send_to(recipient_chain_id);
+        }
+    }
+
+    fn trade_result(
+        market_id: u64,
+        outcome_id: u32,
+        value: Amount,
+        quote: &TradeQuote,
+        prices: Vec<u128>,
+    ) -> TradeResult {
+        TradeResult {
+            market_id,
+            outcome_id,
+            value,
+            shares: Self::units_to_amount(quote.shares),
+            fees: FeeBreakdown {
+                fee: Self::units_to_amount(quote.fee),
+                treasury_fee: Self::units_to_amount(quote.treasury_fee),
+                distributor_fee: Self::units_to_amount(quote.distributor_fee),
+            },
+            prices: prices.into_iter().map(Self::units_to_amount).collect(),
+        }
+    }
+
+    /// Appends a trade to the market's history and publishes it.
+    fn record_trade(&mut self, market: &mut Market, action: TradeAction, trader: AccountOwner, trade: &TradeResult) {
+        let timestamp = self.runtime.system_time();
+
+        let record = TradeRecord {
+            id: market.trade_count,
+            action,
+            trader,
+            outcome_id: trade.outcome_id,
+            value: trade.value,
+            shares: trade.shares,
+            fees: trade.fees.clone(),
+            timestamp,
+            prices: trade.prices.clone(),
+        };
+        self.state.trades.insert(&(market.id, market.trade_count), record).expect("Save trade");
+        market.trade_count += 1;
+
+        self.emit(TruemarketEvent::Trade {
+            action,
+            trader,
+            trade: trade.clone(),
+        });
+    }
+
+    fn emit(&mut self, event: TruemarketEvent) {
+        self.runtime.emit(StreamName::from(MARKET_STREAM), &event);
+        self.record_update(&event);
+    }
+
+    /// Appends an event to this chain's live `updates` feed.
+    fn record_update(&mut self, event: &TruemarketEvent) {
+        let (market_id, kind, outcome_id, amount, prices) = match event {
+            TruemarketEvent::MarketCreated { market_id, prices, .. } => {
+                (*market_id, UpdateKind::MarketCreated, None, None, prices.clone())
+            }
+            TruemarketEvent::Trade { trade, .. } => (
+                trade.market_id,
+                UpdateKind::Trade,
+                Some(trade.outcome_id),
+                Some(trade.value),
+                trade.prices.clone(),
+            ),
+            TruemarketEvent::LiquidityChanged { market_id, shares, .. } => {
+                (*market_id, UpdateKind::LiquidityChanged, None, Some(*shares), Vec::new())
+            }
+            TruemarketEvent::Paused { market_id, paused } => {
+                let kind = if *paused { UpdateKind::Paused } else { UpdateKind::Unpaused };
+                (*market_id, kind, None, None, Vec::new())
+            }
+            TruemarketEvent::Resolved { market_id, outcome_id: None, scalar_value: None } => {
+                (*market_id, UpdateKind::Voided, None, None, Vec::new())
+            }
+            TruemarketEvent::Resolved { market_id, outcome_id, .. } => {
+                (*market_id, UpdateKind::Resolved, *outcome_id, None, Vec::new())
+            }
+            TruemarketEvent::Claimed { market_id, amount, .. } => {
+                (*market_id, UpdateKind::Claimed, None, Some(*amount), Vec::new())
+            }
+        };
+        self.push_update(market_id, kind, outcome_id, amount, prices);
+    }
+
+    fn push_update(
+        &mut self,
+        market_id: u64,
+        kind: UpdateKind,
+        outcome_id: Option<u32>,
+        amount: Option<Amount>,
+        prices: Vec<Amount>,
+    ) {
+        let update = MarketUpdate {
+            id: self.state.updates.count() as u64,
+            market_id,
+            kind,
+            timestamp: self.runtime.system_time(),
+            outcome_id,
+            amount,
+            prices,
+        };
+        self.state.updates.push(update);
+    }
+
+    /// Stores this chain's new balance of an outcome and reports it in the `updates` feed.
+    fn set_my_shares(&mut self, market_id: u64, outcome_id: u32, shares: u128) {
+        self.state.my_shares.insert(&(market_id, outcome_id), shares).expect("Save local");
+        let amount = Some(Self::units_to_amount(shares));
+        self.push_update(market_id, UpdateKind::SharesChanged, Some(outcome_id), amount, Vec::new());
+    }
+
+    /// Takes `amount` of the market's collateral from `from`: tokens, or shares of the parent
+    /// outcome for a conditional market.
+    async fn receive_collateral(&mut self, market: &Market, from: AccountOwner, amount: Amount) {
+        let Some(parent) = market.parent else {
+            self.receive_tokens(market.token.with_abi::<my_fungible::MyFungibleAbi>(), from, amount);
+            return;
+        };
+        let app_owner: AccountOwner = self.runtime.application_id().into();
+        let units = Self::amount_to_units(amount);
+        let chain_id = self.runtime.chain_id();
+        self.transfer_shares(parent.market_id, parent.outcome_id, from, app_owner, units).await;
+        self.burn_receipt(chain_id, parent.market_id, parent.outcome_id, units).await;
+    }
+
+    async fn send_collateral(&mut self, market: &Market, to: AccountOwner, amount: Amount) {
+        let target_account = FungibleAccount {
+            chain_id: self.runtime.chain_id(),
+            owner: to,
+        };
+        self.send_collateral_to(market, target_account, amount).await;
+    }
+
+    /// Pays out `amount` of the market's collateral to an account on any chain. A conditional
+    /// market hands out shares of the parent outcome, or what they are worth in the parent's
+    /// collateral once the parent has settled, so the losing branch pays nothing.
+    async fn send_collateral_to(&mut self, market: &Market, target_account: FungibleAccount, amount: Amount) {
+        let Some(parent) = market.parent else {
+            self.send_tokens_to(market.token.with_abi::<my_fungible::MyFungibleAbi>(), target_account, amount);
+            return;
+        };
+        if amount.is_zero() { return; }
+        let app_owner: AccountOwner = self.runtime.application_id().into();
+        let units = Self::amount_to_units(amount);
+        let mut parent_market = self.state.markets.get(&parent.market_id).await
+            .expect("State error").expect("Parent market not found");
+
+        if matches!(parent_market.state, MarketState::Resolved | MarketState::Voided) {
+            let key = (parent.market_id, parent.outcome_id, app_owner);
+            let held = self.state.market_shares.get(&key).await.expect("E").unwrap_or(0);
+            let remaining = held.checked_sub(units).expect("Insufficient parent shares");
+            self.state.market_shares.insert(&key, remaining).expect("Save");
+
+            let value = Self::redeem_shares(&mut parent_market, parent.outcome_id, units);
+            let token = parent_market.token.with_abi::<my_fungible::MyFungibleAbi>();
+            self.state.markets.insert(&parent.market_id, parent_market).expect("Save market");
+            self.send_tokens_to(token, target_account, Self::units_to_amount(value));
+        } else {
+            let label = parent_market.outcomes[parent.outcome_id as usize].label.clone();
+            self.transfer_shares(parent.market_id, parent.outcome_id, app_owner, target_account.owner, units).await;
+            self.mint_receipt(target_account.chain_id, parent.market_id, parent.outcome_id, units, label).await;
+        }
+    }
+
+    /// Moves outcome shares between two owners in the global ledger.
+    async fn transfer_shares(&mut self, market_id: u64, outcome_id: u32, from: AccountOwner, to: AccountOwner, amount: u128) {
+        let from_key = (market_id, outcome_id, from);
+        let owned = self.state.market_shares.get(&from_key).await.expect("E").unwrap_or(0);
+        assert!(owned >= amount, "Insufficient shares");
+        self.state.market_shares.insert(&from_key, owned - amount).expect("Save");
+
+        let to_key = (market_id, outcome_id, to);
+        let current = self.state.market_shares.get(&to_key).await.expect("E").unwrap_or(0);
+        self.state.market_shares.insert(&to_key, current + amount).expect("Save");
+    }
+
+    /// Burns `amount` shares of an outcome of a settled market and returns their value, taken
+    /// out of the market's balance.
+    fn redeem_shares(market: &mut Market, outcome_id: u32, amount: u128) -> u128 {
+        if amount == 0 {
+            return 0;
+        }
+        let outcome = &mut market.outcomes[outcome_id as usize];
+        let value = if !market.payout_prices.is_empty() {
+            Self::mul_div(amount, market.payout_prices[outcome_id as usize], ONE)
+        } else if market.resolved_outcome_id == Some(outcome_id) {
+            Self::mul_div(amount, Self::amount_to_units(market.balance), outcome.shares_total)
+        } else {
+            0
+        };
+        outcome.shares_total = outcome.shares_total.checked_sub(amount).expect("Under");
+
+        // Rounding must never overdraw the market
+        let value = value.min(Self::amount_to_units(market.balance));
+        market.balance = market.balance.try_sub(Self::units_to_amount(value)).expect("Under");
+        value
+    }
+
+    fn receive_tokens(&mut self, token: ApplicationId<my_fungible::MyFungibleAbi>, from: AccountOwner, amount: Amount) {
+        let app_owner: AccountOwner = self.runtime.application_id().into();
+        let target_account = FungibleAccount {
+            chain_id: self.runtime.chain_id(),
+            owner: app_owner,
+        };
+        let transfer = my_fungible::Operation::Transfer {
+            owner: from,
+            amount,
+            target_account,
+        };
+        self.runtime.call_application(true, token, &transfer);
+    }
+
+    fn send_tokens(&mut self, token: ApplicationId<my_fungible::MyFungibleAbi>, to: AccountOwner, amount: Amount) {
+        let target_account = FungibleAccount {
+            chain_id: self.runtime.chain_id(),
+            owner: to,
+        };
+        self.send_tokens_to(token, target_account, amount);
+    }
+
+    /// Pays out of the application's account to an account on any chain.
+    fn send_tokens_to(
+        &mut self,
+        token: ApplicationId<my_fungible::MyFungibleAbi>,
+        target_account: FungibleAccount,
+        amount: Amount,
+    ) {
+        if amount.is_zero() { return; }
+        let app_owner: AccountOwner = self.runtime.application_id().into();
+        let transfer = my_fungible::Operation::Transfer {
+            owner: app_owner,
+            amount,
+            target_account,
+        };
+        self.runtime.call_application(true, token, &transfer);
+    }
+
+    async fn create_market(
+        &mut self,
+        value: Amount,
+        closes_at: Timestamp,
+        outcomes: Vec<String>,
+        token: ApplicationId,
+        distribution: Vec<u64>,
+        lmsr_liquidity: Option<Amount>,
+        scalar_range: Option<ScalarRange>,
+        parent: Option<ParentPosition>,
+        question: String,
+        image: String,
+        arbitrator: AccountOwner,
+        buy_fees: Fees,
+        sell_fees: Fees,
+        treasury: AccountOwner,
+        distributor: AccountOwner,
+        realitio_timeout: u32,
+        manager: AccountOwner,
+    ) -> u64 {
+        let creator = self.runtime.authenticated_signer().expect("Auth required");
+
+        assert!(!value.is_zero());
+        self.runtime.assert_before(closes_at);
+        assert!((2..=MAX_OUTCOMES as usize).contains(&outcomes.len()), "Invalid number of outcomes");
+        assert!(
+            outcomes.iter().all(|label| !label.trim().is_empty() && label.len() <= MAX_OUTCOME_LABEL_LENGTH),
+            "Outcome labels must be 1 to {MAX_OUTCOME_LABEL_LENGTH} bytes long"
+        );
+        assert!(
+            outcomes.iter().enumerate().all(|(i, label)| !outcomes[..i].contains(label)),
+            "Outcome labels must be unique"
+        );
+        let outcome_count = outcomes.len() as u32;
+        assert!(realitio_timeout >= MINIMUM_REALITIO_TIMEOUT, "Oracle timeout too short");
+        assert!(
+            distribution.is_empty() || distribution.len() == outcomes.len(),
+            "Distribution must have one weight per outcome"
+        );
+        assert!(distribution.iter().all(|weight| *weight > 0), "Distribution weights must be positive");
+        Self::validate_fees(&buy_fees);
+        Self::validate_fees(&sell_fees);
+        if let Some(range) = scalar_range {
+            assert_eq!(outcome_count, 2, "Scalar markets have a short and a long outcome");
+            assert!(range.lower < range.upper, "Empty scalar range");
+        }
+        let market_maker = match lmsr_liquidity {
+            Some(b) => {
+                assert!(!b.is_zero(), "LMSR liquidity must be positive");
+                // The weights size constant-product pools; LMSR prices follow exp(-pool / b)
+                assert!(distribution.is_empty(), "LMSR markets start at even odds");
+                MarketMaker::Lmsr { b: Self::amount_to_units(b) }
+            }
+            None => MarketMaker::Fpmm,
+        };
+
+        if let Some(parent) = parent {
+            let parent_market = self.state.markets.get(&parent.market_id).await
+                .expect("State error").expect("Parent market not found");
+            assert!(parent_market.parent.is_none(), "Parent market is itself conditional");
+            assert!(parent.outcome_id < parent_market.outcome_count, "Invalid parent outcome");
+            assert!(
+                matches!(parent_market.state, MarketState::Open | MarketState::Closed),
+                "Parent market already resolved"
+            );
+            // Oracle bonds are still paid in tokens, the ones the parent trades in
+            assert_eq!(parent_market.token, token, "Token must match the parent market");
+        }
+
+        let market_id = *self.state.market_index.get();
+        let question_id = format!("q_{}_{}", market_id, question);
+
+        let mut market = Market {
+            id: market_id,
+            closes_at_timestamp: closes_at,
+            balance: Amount::ZERO,
             liquidity: 0,
             shares_available: 0,
             state: MarketState::Open,
+            market_maker,
             buy_fees,
             sell_fees,
             treasury,
             distributor,
             fee_accumulator: 0,
+            fees_per_share: 0,
+            trade_count: 0,
             question,
             question_id,
             arbitrator,
             realitio_timeout,
-            outcome_count: outcomes,
+            resolved_outcome_id: None,
+            scalar_range,
+            resolved_value: None,
+            payout_prices: Vec::new(),
+            outcome_count,
             outcomes: Vec::new(),
             token,
+            parent,
+            event_id: None,
             manager,
             creator,
             paused: false,
             image,
         };
 
-        for i in 0..outcomes {
+        for (i, label) in outcomes.iter().enumerate() {
             market.outcomes.push(MarketOutcome {
-                id: i,
+                id: i as u32,
+                label: label.clone(),
                 shares_total: 0,
                 shares_available: 0,
             });
         }
 
+        self.receive_collateral(&market, creator, value).await;
+
         let value_units = Self::amount_to_units(value);
-        self.add_initial_liquidity(&mut market, value_units);
+        let send_back = self.add_initial_liquidity(&mut market, value_units, &distribution);
+        if let MarketMaker::Lmsr { b } = market_maker {
+            // Every pool starts at `value` and must survive the market maker's worst-case loss
+            let min_pool = Self::pools(&market).into_iter().min().unwrap_or(0);
+            assert!(min_pool >= Lmsr { b }.max_loss(outcome_count), "Funding below the LMSR loss bound");
+        }
+        self.state.liquidity_shares.insert(&(market_id, creator), value_units).expect("Save");
+        for (outcome_id, amount) in send_back.into_iter().enumerate() {
+            if amount > 0 {
+                self.credit_shares(market_id, outcome_id as u32, creator, amount).await;
+            }
+        }
 
+        let token = market.token;
+        let prices = self.calc_prices(&market).into_iter().map(Self::units_to_amount).collect();
         self.state.markets.insert(&market_id, market).expect("Save market");
         self.state.market_index.set(market_id + 1);
+        self.emit(TruemarketEvent::MarketCreated {
+            market_id,
+            creator,
+            token,
+            outcomes,
+            closes_at,
+            prices,
+        });
+
+        // Listing indices
+        self.state.markets_by_state.insert(index_key(state_prefix(MarketState::Open), market_id), ());
+        self.state.markets_by_creator.insert(index_key(creator_prefix(&creator), market_id), ());
+        self.state.markets_by_token.insert(index_key(token_prefix(&token), market_id), ());
+        self.state.markets_by_close.insert(index_key(close_prefix(closes_at), market_id), ());
+        market_id
     }
 
-    fn add_initial_liquidity(&self, market: &mut Market, value_units: u128) {
+    /// Mints `value_units` complete sets and seeds the pools with them, weighted by
+    /// `distribution` (uniform when empty). Returns the outcome shares left out of each pool,
+    /// which belong to the creator.
+    fn add_initial_liquidity(&self, market: &mut Market, value_units: u128, distribution: &[u64]) -> Vec<u128> {
         market.liquidity += value_units;
         market.balance = market.balance.try_add(Self::units_to_amount(value_units)).expect("Over");
-        for outcome in &mut market.outcomes {
-            outcome.shares_available += value_units;
+
+        let max_weight = distribution.iter().copied().max().unwrap_or(0) as u128;
+        let mut send_back = Vec::with_capacity(market.outcomes.len());
+        for (i, outcome) in market.outcomes.iter_mut().enumerate() {
+            let pool = if distribution.is_empty() {
+                value_units
+            } else {
+                Self::mul_div(value_units, distribution[i] as u128, max_weight)
+            };
+            outcome.shares_available += pool;
             outcome.shares_total += value_units;
-            market.shares_available += value_units;
+            market.shares_available += pool;
+            send_back.push(value_units - pool);
         }
+        send_back
     }
 
     fn add_shares_to_market(&self, market: &mut Market, amount_units: u128) {
@@ -416,19 +1848,21 @@ impl TruemarketContract {
         market.balance = market.balance.try_add(Self::units_to_amount(amount_units)).expect("Over");
     }
 
-    fn calc_buy_amount(&self, market: &Market, amount_units: u128, outcome_id: u32) -> u128 {
-        let buy_pool = market.outcomes[outcome_id as usize].shares_available;
-        let mut ending_balance = BigUint::from(buy_pool);
-
-        for (i, outcome) in market.outcomes.iter().enumerate() {
-            if i as u32 != outcome_id {
-                let shares = BigUint::from(outcome.shares_available);
-                let denom = &shares + BigUint::from(amount_units);
-                let num = &ending_balance * &shares;
-                ending_balance = (num + &denom - BigUint::from(1u32)) / denom;
-            }
+    fn remove_shares_from_market(&self, market: &mut Market, amount_units: u128) {
+        for outcome in &mut market.outcomes {
+            outcome.shares_available = outcome.shares_available
+                .checked_sub(amount_units).expect("Insufficient liquidity");
+            outcome.shares_total -= amount_units;
+            market.shares_available -= amount_units;
         }
-        let ending_u128 = ending_balance.to_u128().expect("Overflow");
-        buy_pool.checked_add(amount_units).expect("Over").checked_sub(ending_u128).expect("Under")
+        market.balance = market.balance.try_sub(Self::units_to_amount(amount_units)).expect("Under");
+    }
+
+    fn pools(market: &Market) -> Vec<u128> {
+        market.outcomes.iter().map(|outcome| outcome.shares_available).collect()
+    }
+
+    fn calc_prices(&self, market: &Market) -> Vec<u128> {
+        market.market_maker.prices(&Self::pools(market))
     }
 }
\ No newline at end of file
@@ -0,0 +1,85 @@
+//! Keys of the market listing indices kept in `ByteMapView`s.
+//!
+//! A key is a filter prefix followed by the big-endian market id, so that a prefix scan
+//! returns the matching markets in creation order.
+
+use linera_sdk::linera_base_types::{AccountOwner, ApplicationId, Timestamp};
+
+use crate::MarketState;
+
+/// Key prefix of a market state in `markets_by_state`.
+pub fn state_prefix(state: MarketState) -> Vec<u8> {
+    vec![state as u8]
+}
+
+/// Key prefix of a creator in `markets_by_creator`.
+pub fn creator_prefix(creator: &AccountOwner) -> Vec<u8> {
+    let mut prefix = creator.to_string().into_bytes();
+    prefix.push(0);
+    prefix
+}
+
+/// Key prefix of a token in `markets_by_token`.
+pub fn token_prefix(token: &ApplicationId) -> Vec<u8> {
+    let mut prefix = token.to_string().into_bytes();
+    prefix.push(0);
+    prefix
+}
+
+/// Key prefix of a closing time in `markets_by_close`. Big-endian, so keys sort by time.
+pub fn close_prefix(closes_at: Timestamp) -> Vec<u8> {
+    closes_at.micros().to_be_bytes().to_vec()
+}
+
+/// A listing index key: the filter's prefix followed by the big-endian market id, so
+/// that the markets under one prefix come out in creation order.
+pub fn index_key(mut prefix: Vec<u8>, market_id: u64) -> Vec<u8> {
+    prefix.extend_from_slice(&market_id.to_be_bytes());
+    prefix
+}
+
+/// The market id at the end of a listing index key.
+pub fn index_market_id(key: &[u8]) -> u64 {
+    let bytes = key[key.len() - 8..].try_into().expect("Index key too short");
+    u64::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_round_trip_the_market_id() {
+        for market_id in [0, 1, 255, 256, u64::MAX] {
+            assert_eq!(index_market_id(&index_key(state_prefix(MarketState::Closed), market_id)), market_id);
+            assert_eq!(index_market_id(&index_key(close_prefix(Timestamp::from(42)), market_id)), market_id);
+        }
+    }
+
+    #[test]
+    fn keys_sort_by_market_id_within_a_prefix() {
+        let keys: Vec<_> = [1, 255, 256, 70_000]
+            .into_iter()
+            .map(|market_id| index_key(state_prefix(MarketState::Open), market_id))
+            .collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn close_keys_sort_by_time() {
+        // Earlier closing times sort first whatever the market ids
+        let keys = [
+            index_key(close_prefix(Timestamp::from(5)), 900),
+            index_key(close_prefix(Timestamp::from(255)), 3),
+            index_key(close_prefix(Timestamp::from(256)), 2),
+            index_key(close_prefix(Timestamp::from(256)), 7),
+            index_key(close_prefix(Timestamp::from(1 << 40)), 0),
+        ];
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // The closing scan stops at the first key that isn't below the bound
+        let before = close_prefix(Timestamp::from(256));
+        let closing = keys.iter().take_while(|key| key.as_slice() < before.as_slice()).count();
+        assert_eq!(closing, 2);
+    }
+}
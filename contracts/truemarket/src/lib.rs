@@ -5,11 +5,16 @@ use linera_sdk::{
 };
 use serde::{Deserialize, Serialize};
 
+pub mod amm;
+pub mod index;
+pub mod liquidity;
+pub mod neg_risk;
+
 pub struct TruemarketAbi;
 
 impl ContractAbi for TruemarketAbi {
     type Operation = Operation;
-    type Response = ();
+    type Response = TruemarketResponse;
 }
 
 impl ServiceAbi for TruemarketAbi {
@@ -22,9 +27,20 @@ pub enum Operation {
     CreateMarket {
         value: Amount,
         closes_at: Timestamp,
-        outcomes: u32,
+        // One label per outcome, e.g. ["Yes", "No"]
+        outcomes: Vec<String>,
         token: ApplicationId, 
+        // Starting pool weight per outcome (empty = even odds). A heavier pool is cheaper.
         distribution: Vec<u64>,
+        // Prices the market with Hanson's LMSR and this liquidity parameter `b` instead of the
+        // constant-product pools. LMSR markets start at even odds, so `distribution` must be
+        // empty, and `value` must cover the loss bound b * ln(outcomes).
+        lmsr_liquidity: Option<Amount>,
+        // Makes this a two-outcome scalar market (`SCALAR_SHORT`, `SCALAR_LONG`) over this range
+        scalar_range: Option<ScalarRange>,
+        // Makes this a conditional market: its collateral is shares of this outcome of another
+        // market, which must trade in `token`, instead of `token` itself
+        parent: Option<ParentPosition>,
         question: String,
         image: String,
         arbitrator: AccountOwner,
@@ -43,6 +59,202 @@ pub enum Operation {
         // ADDED: Required so the User Chain knows what token to send
         token: ApplicationId, 
     },
+    Sell {
+        market_id: u64,
+        outcome_id: u32,
+        // Amount of tokens the seller wants to receive, net of fees
+        value: Amount,
+        max_outcome_shares_to_sell: Amount,
+    },
+    /// Moves an open market to `Closed` once its closing time has passed.
+    CloseMarket {
+        market_id: u64,
+    },
+    /// Records the winning outcome of a closed market. Only the arbitrator may call this.
+    ResolveMarket {
+        market_id: u64,
+        outcome_id: u32,
+    },
+    /// Records the final value of a closed scalar market. Only the arbitrator may call this.
+    ResolveScalarMarket {
+        market_id: u64,
+        value: i64,
+    },
+    /// Burns the caller's winning shares of a resolved market and pays out their collateral.
+    ClaimWinnings {
+        market_id: u64,
+    },
+    /// Resolves a closed market as invalid. Only the arbitrator may call this.
+    /// Shares then redeem at the final implied prices, or at 1/N each with `equal_split`.
+    VoidMarket {
+        market_id: u64,
+        equal_split: bool,
+    },
+    /// Burns the caller's shares of every outcome of a voided market at its payout prices.
+    ClaimVoidedShares {
+        market_id: u64,
+    },
+    /// Deposits collateral into an open market's pools in exchange for LP shares.
+    AddLiquidity {
+        market_id: u64,
+        value: Amount,
+    },
+    /// Burns LP shares for collateral, plus outcome shares when the pools are imbalanced.
+    RemoveLiquidity {
+        market_id: u64,
+        shares: Amount,
+    },
+    /// Pays the caller's share of the LP fees collected by a market.
+    ClaimFees {
+        market_id: u64,
+    },
+    /// Mints `value` shares of every outcome for `value` collateral, bypassing the pools.
+    SplitPosition {
+        market_id: u64,
+        value: Amount,
+    },
+    /// Burns `shares` of every outcome for as much collateral, bypassing the pools.
+    MergePositions {
+        market_id: u64,
+        shares: Amount,
+    },
+    /// Halts trading and new liquidity on a market. Only the manager may call this.
+    PauseMarket {
+        market_id: u64,
+    },
+    /// Resumes a paused market. Only the manager may call this.
+    UnpauseMarket {
+        market_id: u64,
+    },
+    /// Updates the given market parameters. Only the manager may call this.
+    UpdateMarket {
+        market_id: u64,
+        params: UpdateMarketParams,
+    },
+    /// Proposes the answer to a closed market's question, backed by a bond. A later answer
+    /// must at least double the previous bond and arrive before the previous one finalizes.
+    SubmitAnswer {
+        market_id: u64,
+        outcome_id: u32,
+        bond: Amount,
+    },
+    /// Resolves the market with the last answer once `realitio_timeout` has passed unchallenged.
+    FinalizeAnswer {
+        market_id: u64,
+    },
+    /// Hands the question over to the arbitrator, whose ruling then settles the bonds.
+    /// The requester pays the arbitrator a `fee` of at least the current best bond.
+    RequestArbitration {
+        market_id: u64,
+        fee: Amount,
+    },
+    /// Follows the market chain's events from a user chain. They land in the `updates` feed,
+    /// so a client listening to the chain's block notifications can fetch what changed.
+    SubscribeToMarkets,
+    UnsubscribeFromMarkets,
+    /// Groups open yes/no markets managed by the caller into one event, e.g. one market per
+    /// candidate. They must share their token, arbitrator and closing time.
+    CreateEvent {
+        title: String,
+        closes_at: Timestamp,
+        market_ids: Vec<u64>,
+    },
+    /// Resolves every market of an event at once: `market_id` to yes, all others to no.
+    /// Only the arbitrator may call this.
+    ResolveEvent {
+        event_id: u64,
+        market_id: u64,
+    },
+    /// Negative-risk conversion: burns `shares` no shares of each of `market_ids` for as many
+    /// yes shares of every other market of the event, plus `shares * (len - 1)` collateral.
+    ConvertPositions {
+        event_id: u64,
+        market_ids: Vec<u64>,
+        shares: Amount,
+    },
+}
+
+/// What an operation reports back to its caller, e.g. another application using
+/// `call_application`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub enum TruemarketResponse {
+    /// The operation completed and has nothing to report.
+    Ok,
+    /// The operation was sent to the market chain; its outcome arrives as a receipt.
+    Forwarded,
+    MarketCreated { market_id: u64 },
+    Bought(TradeResult),
+    Sold(TradeResult),
+    /// Collateral paid out for winning or voided shares.
+    Claimed { amount: Amount },
+    LiquidityAdded { shares: Amount },
+    LiquidityRemoved { value: Amount },
+    FeesClaimed { amount: Amount },
+    EventCreated { event_id: u64 },
+    /// Collateral released by a negative-risk conversion.
+    Converted { value: Amount },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct TradeResult {
+    pub market_id: u64,
+    pub outcome_id: u32,
+    /// Tokens paid in for a buy, or received for a sell.
+    pub value: Amount,
+    pub shares: Amount,
+    pub fees: FeeBreakdown,
+    /// Implied probability of every outcome after the trade (1.0 == `Amount::ONE`).
+    pub prices: Vec<Amount>,
+}
+
+/// Published on `MARKET_STREAM` by the market chain whenever a market changes, so that
+/// indexers and user chains can follow the activity without polling.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub enum TruemarketEvent {
+    MarketCreated {
+        market_id: u64,
+        creator: AccountOwner,
+        token: ApplicationId,
+        outcomes: Vec<String>,
+        closes_at: Timestamp,
+        prices: Vec<Amount>,
+    },
+    Trade {
+        action: TradeAction,
+        trader: AccountOwner,
+        trade: TradeResult,
+    },
+    /// LP shares were minted (`added`) or burned; `liquidity` is the market's new total.
+    LiquidityChanged {
+        market_id: u64,
+        provider: AccountOwner,
+        added: bool,
+        shares: Amount,
+        liquidity: Amount,
+    },
+    Paused {
+        market_id: u64,
+        paused: bool,
+    },
+    /// The market was resolved to `outcome_id` or, for a scalar market, to `scalar_value`.
+    /// Both are `None` when it was voided.
+    Resolved {
+        market_id: u64,
+        outcome_id: Option<u32>,
+        scalar_value: Option<i64>,
+    },
+    Claimed {
+        market_id: u64,
+        owner: AccountOwner,
+        amount: Amount,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, SimpleObject)]
+pub struct FeeBreakdown {
+    pub fee: Amount,
+    pub treasury_fee: Amount,
+    pub distributor_fee: Amount,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -55,32 +267,215 @@ pub enum Message {
         value: Amount,
         // Added to support receipts
         return_chain_id: ChainId, 
+        // The token pushed to the market chain, so a failed buy can be refunded
+        token: ApplicationId,
     },
     // Receipt message sent back to the user
     ShareMinted {
         market_id: u64,
         outcome_id: u32,
         amount: u128,
-    }
+        // The user chain has no copy of the market, so the receipt names the outcome
+        label: String,
+    },
+    Sell {
+        market_id: u64,
+        outcome_id: u32,
+        value: Amount,
+        max_outcome_shares_to_sell: Amount,
+        owner: AccountOwner,
+        return_chain_id: ChainId,
+    },
+    // Claims winnings or voided shares, depending on how the market was resolved
+    Claim {
+        market_id: u64,
+        owner: AccountOwner,
+        return_chain_id: ChainId,
+    },
+    // Receipt sent back to the user when shares were sold or redeemed
+    ShareBurned {
+        market_id: u64,
+        outcome_id: u32,
+        amount: u128,
+    },
+    // Sent by the user chain when its Buy bounced: returns the pushed tokens
+    Refund {
+        owner: AccountOwner,
+        token: ApplicationId,
+        value: Amount,
+        return_chain_id: ChainId,
+    },
 }
 
 #[derive(
     Debug, Deserialize, Serialize, Clone, Default, SimpleObject, InputObject
 )]
+#[graphql(input_name = "FeesInput")]
 pub struct Fees {
     pub fee: u64,
     pub treasury_fee: u64,
     pub distributor_fee: u64,
 }
 
+impl Fees {
+    /// Sum of all fee rates, in `FEE_DENOMINATOR` units.
+    pub fn total(&self) -> u128 {
+        self.fee as u128 + self.treasury_fee as u128 + self.distributor_fee as u128
+    }
+
+    /// Splits `value` into (LP fee, treasury fee, distributor fee).
+    pub fn split(&self, value: u128) -> (u128, u128, u128) {
+        (
+            (value * self.fee as u128) / FEE_DENOMINATOR,
+            (value * self.treasury_fee as u128) / FEE_DENOMINATOR,
+            (value * self.distributor_fee as u128) / FEE_DENOMINATOR,
+        )
+    }
+}
+
+/// Market parameters changed by `UpdateMarket`; `None` fields are left untouched.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, InputObject)]
+pub struct UpdateMarketParams {
+    pub image: Option<String>,
+    pub treasury: Option<AccountOwner>,
+    pub distributor: Option<AccountOwner>,
+    pub buy_fees: Option<Fees>,
+    pub sell_fees: Option<Fees>,
+}
+
+/// Bounds of a scalar market. A resolution at or below `lower` pays everything to
+/// `SCALAR_SHORT`, at or above `upper` everything to `SCALAR_LONG`, linearly in between.
+#[derive(
+    Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, SimpleObject, InputObject
+)]
+#[graphql(input_name = "ScalarRangeInput")]
+pub struct ScalarRange {
+    pub lower: i64,
+    pub upper: i64,
+}
+
+impl ScalarRange {
+    /// Redemption price per share of (short, long) for a resolution `value` (scaled by `ONE`).
+    pub fn payout_prices(&self, value: i64) -> Vec<u128> {
+        let value = value.clamp(self.lower, self.upper);
+        let span = (self.upper as i128 - self.lower as i128) as u128;
+        let long = (value as i128 - self.lower as i128) as u128 * ONE / span;
+        vec![ONE - long, long]
+    }
+}
+
+/// The outcome of a parent market whose shares collateralize a conditional market.
+#[derive(
+    Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, SimpleObject, InputObject
+)]
+#[graphql(input_name = "ParentPositionInput")]
+pub struct ParentPosition {
+    pub market_id: u64,
+    pub outcome_id: u32,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, async_graphql::Enum)]
 pub enum MarketState {
     Open,
     Closed,
     Resolved,
+    Voided,
+}
+
+/// The kind of remote trade recorded in a failure receipt.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, async_graphql::Enum)]
+pub enum TradeAction {
+    Buy,
+    Sell,
+    Claim,
 }
 
+/// A manager change recorded in the audit log.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, async_graphql::Enum)]
+pub enum MarketAction {
+    Paused,
+    Unpaused,
+    ImageUpdated,
+    TreasuryUpdated,
+    DistributorUpdated,
+    BuyFeesUpdated,
+    SellFeesUpdated,
+}
+
+/// What changed in an entry of the live `updates` feed.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, async_graphql::Enum)]
+pub enum UpdateKind {
+    MarketCreated,
+    Trade,
+    LiquidityChanged,
+    Paused,
+    Unpaused,
+    Resolved,
+    Voided,
+    Claimed,
+    /// This chain's own share balance changed.
+    SharesChanged,
+}
+
+/// Name of the event stream the market chain publishes `TruemarketEvent`s on.
+pub const MARKET_STREAM: &[u8] = b"markets";
+
 pub const MAX_OUTCOMES: u32 = 32;
+pub const MAX_OUTCOME_LABEL_LENGTH: usize = 64;
+/// Outcome ids of the yes/no markets grouped in an event.
+pub const OUTCOME_YES: u32 = 0;
+pub const OUTCOME_NO: u32 = 1;
+/// Outcome ids of a scalar market.
+pub const SCALAR_SHORT: u32 = 0;
+pub const SCALAR_LONG: u32 = 1;
 pub const MAX_FEE: u64 = 500;
 pub const MINIMUM_REALITIO_TIMEOUT: u32 = 3600;
-pub const FEE_DENOMINATOR: u128 = 10_000;
\ No newline at end of file
+pub const FEE_DENOMINATOR: u128 = 10_000;
+/// Fixed-point scale for outcome prices (1.0 == ONE).
+pub const ONE: u128 = 1_000_000_000_000_000_000;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_complete(prices: &[u128]) {
+        assert_eq!(prices[SCALAR_SHORT as usize] + prices[SCALAR_LONG as usize], ONE);
+    }
+
+    #[test]
+    fn scalar_payouts_clamp_to_the_range() {
+        let range = ScalarRange { lower: -100, upper: 300 };
+        for value in [-100, -101, i64::MIN] {
+            let prices = range.payout_prices(value);
+            assert_eq!(prices, vec![ONE, 0]);
+        }
+        for value in [300, 301, i64::MAX] {
+            let prices = range.payout_prices(value);
+            assert_eq!(prices, vec![0, ONE]);
+        }
+    }
+
+    #[test]
+    fn scalar_payouts_are_linear_inside_the_range() {
+        let range = ScalarRange { lower: -100, upper: 300 };
+        assert_eq!(range.payout_prices(100), vec![ONE / 2, ONE / 2]);
+
+        let prices = range.payout_prices(0);
+        assert_eq!(prices[SCALAR_LONG as usize], ONE / 4);
+        assert_complete(&prices);
+
+        let prices = ScalarRange { lower: 0, upper: 3 }.payout_prices(1);
+        assert_complete(&prices);
+    }
+
+    #[test]
+    fn scalar_payouts_handle_the_widest_range() {
+        let range = ScalarRange { lower: i64::MIN, upper: i64::MAX };
+        for value in [i64::MIN, i64::MIN + 1, -1, 0, 1, i64::MAX - 1, i64::MAX] {
+            assert_complete(&range.payout_prices(value));
+        }
+        assert_eq!(range.payout_prices(i64::MIN), vec![ONE, 0]);
+        assert_eq!(range.payout_prices(i64::MAX), vec![0, ONE]);
+        // The midpoint of an odd span rounds the long side down
+        assert_eq!(range.payout_prices(0)[SCALAR_LONG as usize], ONE / 2);
+    }
+}
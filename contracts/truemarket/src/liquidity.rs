@@ -0,0 +1,121 @@
+//! LP accounting: minting and burning LP shares against a market's pools, and the
+//! reward-per-share bookkeeping of LP fees.
+//!
+//! Like `amm`, every function is pure and returns `None` on overflow.
+
+use crate::amm::mul_div;
+use crate::ONE;
+
+/// LP shares minted for adding `value` to `pools` backed by `liquidity` LP shares, and the
+/// outcome shares sent back to the provider.
+///
+/// LP shares are minted in proportion to the existing supply; only the first provider gets
+/// them one-for-one. An imbalanced pool only takes the fraction of each outcome that keeps
+/// prices unchanged, the rest goes back to the provider.
+pub fn add_amounts(pools: &[u128], liquidity: u128, value: u128) -> Option<(u128, Vec<u128>)> {
+    let pool_weight = pools.iter().copied().max().unwrap_or(0);
+    let shares = if liquidity == 0 || pool_weight == 0 {
+        value
+    } else {
+        mul_div(value, liquidity, pool_weight)?
+    };
+
+    let send_back = if pools.iter().all(|pool| *pool == pool_weight) {
+        vec![0; pools.len()]
+    } else {
+        pools
+            .iter()
+            .map(|pool| value.checked_sub(mul_div(value, *pool, pool_weight)?))
+            .collect::<Option<Vec<u128>>>()?
+    };
+    Some((shares, send_back))
+}
+
+/// The part of every pool owned by `shares` out of `liquidity` LP shares.
+pub fn pool_shares(pools: &[u128], liquidity: u128, shares: u128) -> Option<Vec<u128>> {
+    pools.iter().map(|pool| mul_div(shares, *pool, liquidity)).collect()
+}
+
+/// Splits the part of the pools owned by `shares` into the complete sets burned for
+/// collateral and the outcome shares left over on the larger pools.
+pub fn remove_amounts(pools: &[u128], liquidity: u128, shares: u128) -> Option<(u128, Vec<u128>)> {
+    let owned = pool_shares(pools, liquidity, shares)?;
+    let complete_sets = owned.iter().copied().min().unwrap_or(0);
+    let send_back = owned.iter().map(|amount| amount - complete_sets).collect();
+    Some((complete_sets, send_back))
+}
+
+/// Growth of a market's fees per LP share (scaled by `ONE`) when `fee` is paid to
+/// `liquidity` LP shares.
+pub fn fees_per_share(fee: u128, liquidity: u128) -> Option<u128> {
+    mul_div(fee, ONE, liquidity)
+}
+
+/// Fees earned by `lp_shares` since the market's creation at `fees_per_share`. A provider
+/// is owed this minus what was already settled.
+pub fn earned_fees(lp_shares: u128, fees_per_share: u128) -> Option<u128> {
+    mul_div(lp_shares, fees_per_share, ONE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amm::{calc_buy_amount, calc_prices};
+
+    #[test]
+    fn balanced_add_mints_against_the_supply() {
+        // Pools grew past the LP supply, e.g. after LPs left a resolved-risk market
+        let (shares, send_back) = add_amounts(&[200 * ONE, 200 * ONE], 100 * ONE, 50 * ONE).unwrap();
+        assert_eq!(shares, 25 * ONE);
+        assert_eq!(send_back, vec![0, 0]);
+
+        let (shares, _) = add_amounts(&[0, 0], 0, 50 * ONE).unwrap();
+        assert_eq!(shares, 50 * ONE);
+    }
+
+    #[test]
+    fn imbalanced_add_then_full_remove_returns_the_value() {
+        let liquidity = 100 * ONE;
+        let mut pools = vec![100 * ONE, 100 * ONE, 100 * ONE];
+
+        // A trade leaves the pools imbalanced
+        let bought = calc_buy_amount(&pools, 30 * ONE, 1).unwrap();
+        pools.iter_mut().for_each(|pool| *pool += 30 * ONE);
+        pools[1] -= bought;
+        let prices = calc_prices(&pools);
+
+        let value = 40 * ONE;
+        let (shares, added_back) = add_amounts(&pools, liquidity, value).unwrap();
+        let pools: Vec<u128> = pools.iter().zip(&added_back).map(|(pool, back)| pool + value - back).collect();
+        assert!(added_back.iter().any(|back| *back > 0));
+        // Adding liquidity must not move prices
+        for (before, after) in prices.iter().zip(calc_prices(&pools)) {
+            assert!(before.abs_diff(after) <= 1_000);
+        }
+
+        let (complete_sets, removed_back) = remove_amounts(&pools, liquidity + shares, shares).unwrap();
+        for (added, removed) in added_back.iter().zip(&removed_back) {
+            // Every outcome comes back in full, up to rounding in the pools' favour
+            let returned = complete_sets + added + removed;
+            assert!(returned <= value);
+            assert!(value - returned <= 10);
+        }
+    }
+
+    #[test]
+    fn fees_go_to_the_lps_holding_shares_when_accrued() {
+        // A provides 100 LP shares, a trade pays 10 in fees
+        let mut liquidity = 100 * ONE;
+        let mut accumulated = fees_per_share(10 * ONE, liquidity).unwrap();
+
+        // B provides 300 LP shares and starts with the fees earned so far settled
+        let debt_b = earned_fees(300 * ONE, accumulated).unwrap();
+        liquidity += 300 * ONE;
+        accumulated += fees_per_share(40 * ONE, liquidity).unwrap();
+
+        let owed_a = earned_fees(100 * ONE, accumulated).unwrap();
+        let owed_b = earned_fees(300 * ONE, accumulated).unwrap() - debt_b;
+        assert_eq!(owed_a, 20 * ONE);
+        assert_eq!(owed_b, 30 * ONE);
+    }
+}
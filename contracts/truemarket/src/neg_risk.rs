@@ -0,0 +1,141 @@
+//! Negative-risk accounting of events, whose yes/no markets resolve to yes for exactly
+//! one of them.
+//!
+//! Converting `amount` no shares in `k` markets of an event into yes shares of all the
+//! others releases `amount * (k - 1)` collateral and leaves the yes shares unbacked in
+//! their own markets. The markets only add up as a whole, which is settled once the
+//! winner is known.
+
+/// Collateral taken out of each market's balance when `amount` no shares are converted
+/// in the markets flagged in `converted`: `amount` from all but one of them.
+pub fn conversion_debits(converted: &[bool], amount: u128) -> Vec<u128> {
+    let mut to_release = converted.iter().filter(|converted| **converted).count().saturating_sub(1);
+    converted
+        .iter()
+        .map(|converted| {
+            if *converted && to_release > 0 {
+                to_release -= 1;
+                amount
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+/// The balances of an event's markets once `winner` resolved to yes: every losing market
+/// keeps exactly what its no shares redeem for, the winner takes the rest. Returns `None`
+/// when the markets together hold less than the losing sides are owed.
+pub fn settle_balances(balances: &[u128], no_totals: &[u128], winner: usize) -> Option<Vec<u128>> {
+    let total = balances.iter().try_fold(0u128, |sum, balance| sum.checked_add(*balance))?;
+    let losing = no_totals
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != winner)
+        .try_fold(0u128, |sum, (_, no_total)| sum.checked_add(*no_total))?;
+    let winning = total.checked_sub(losing)?;
+
+    Some(
+        no_totals
+            .iter()
+            .enumerate()
+            .map(|(i, no_total)| if i == winner { winning } else { *no_total })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ONE;
+
+    /// An event's markets as the contract tracks them.
+    struct Markets {
+        balances: Vec<u128>,
+        yes_totals: Vec<u128>,
+        no_totals: Vec<u128>,
+        released: u128,
+    }
+
+    impl Markets {
+        /// Markets that only ever minted complete sets.
+        fn new(complete_sets: &[u128]) -> Self {
+            Markets {
+                balances: complete_sets.to_vec(),
+                yes_totals: complete_sets.to_vec(),
+                no_totals: complete_sets.to_vec(),
+                released: 0,
+            }
+        }
+
+        fn convert(&mut self, market_ids: &[usize], amount: u128) {
+            let converted: Vec<bool> = (0..self.balances.len()).map(|i| market_ids.contains(&i)).collect();
+            let debits = conversion_debits(&converted, amount);
+            assert_eq!(debits.iter().sum::<u128>(), amount * (market_ids.len() as u128 - 1));
+
+            for (i, debit) in debits.into_iter().enumerate() {
+                if converted[i] {
+                    self.no_totals[i] = self.no_totals[i].checked_sub(amount).unwrap();
+                    self.balances[i] = self.balances[i].checked_sub(debit).unwrap();
+                    self.released += debit;
+                } else {
+                    self.yes_totals[i] += amount;
+                }
+            }
+        }
+
+        fn assert_settles_for_every_winner(&self) {
+            let total: u128 = self.balances.iter().sum();
+            for winner in 0..self.balances.len() {
+                let settled = settle_balances(&self.balances, &self.no_totals, winner).unwrap();
+                assert_eq!(settled.iter().sum::<u128>(), total);
+                for (i, balance) in settled.iter().enumerate() {
+                    // Each market pays out exactly what its winning side redeems for
+                    let owed = if i == winner { self.yes_totals[i] } else { self.no_totals[i] };
+                    assert_eq!(*balance, owed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn debits_leave_one_converted_market_untouched() {
+        assert_eq!(conversion_debits(&[true, false, true, true], 5), vec![5, 0, 5, 0]);
+        assert_eq!(conversion_debits(&[false, true, false], 5), vec![0, 0, 0]);
+        assert_eq!(conversion_debits(&[], 5), Vec::<u128>::new());
+    }
+
+    #[test]
+    fn single_conversion_settles_for_every_winner() {
+        let mut markets = Markets::new(&[10 * ONE, 10 * ONE, 10 * ONE]);
+        markets.convert(&[0], 4 * ONE);
+        assert_eq!(markets.released, 0);
+        markets.assert_settles_for_every_winner();
+    }
+
+    #[test]
+    fn repeated_conversions_settle_for_every_winner() {
+        let mut markets = Markets::new(&[10 * ONE, 25 * ONE, 7 * ONE, 40 * ONE]);
+        markets.convert(&[1, 3], 6 * ONE);
+        markets.convert(&[0, 1, 2], 5 * ONE);
+        markets.convert(&[2], 2 * ONE);
+        markets.convert(&[0, 1, 3], ONE + 1);
+        assert_eq!(markets.released, 6 * ONE + 10 * ONE + 2 * (ONE + 1));
+        markets.assert_settles_for_every_winner();
+    }
+
+    #[test]
+    fn converting_every_no_share_settles() {
+        // Every no share of every market is converted at once
+        let mut markets = Markets::new(&[3 * ONE, 3 * ONE]);
+        markets.convert(&[0, 1], 3 * ONE);
+        assert_eq!(markets.no_totals, vec![0, 0]);
+        markets.assert_settles_for_every_winner();
+    }
+
+    #[test]
+    fn settling_rejects_missing_collateral() {
+        assert_eq!(settle_balances(&[5, 5], &[1, 11], 0), None);
+        assert_eq!(settle_balances(&[5, 5], &[6, 4], 0), Some(vec![6, 4]));
+    }
+}
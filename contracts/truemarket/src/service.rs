@@ -8,13 +8,22 @@ use async_graphql::{
     Context, EmptySubscription, Object, Request, Response, Schema, SimpleObject,
 };
 use linera_sdk::{
-    graphql::GraphQLMutationRoot, linera_base_types::WithServiceAbi, views::View, Service,
-    ServiceRuntime,
+    graphql::GraphQLMutationRoot,
+    linera_base_types::{AccountOwner, Amount, ApplicationId, Timestamp, WithServiceAbi},
+    views::{View, ViewError},
+    Service, ServiceRuntime,
 };
 
-use truemarket::{Operation, TruemarketAbi, MarketState, MAX_OUTCOMES};
+use truemarket::amm::{self, MarketMaker, PricingEngine, TradeQuote};
+use truemarket::index::{close_prefix, creator_prefix, index_market_id, state_prefix, token_prefix};
+use truemarket::{
+    FeeBreakdown, Fees, Operation, ParentPosition, ScalarRange, TruemarketAbi, MarketState,
+    MAX_OUTCOMES, ONE,
+};
 
-use self::state::{Market, TruemarketState};
+use self::state::{
+    AuditEntry, Event, Market, MarketUpdate, Question, TradeFailure, TradeRecord, TruemarketState,
+};
 
 pub struct TruemarketService {
     state: Arc<TruemarketState>,
@@ -58,18 +67,221 @@ impl Service for TruemarketService {
 struct MarketView {
     id: u64,
     question: String,
+    question_id: String,
     image: String,
     outcome_count: u32,
     state: MarketState,
+    /// Liquidity parameter `b` when the market is priced by LMSR instead of the FPMM
+    lmsr_liquidity: Option<Amount>,
+    resolved_outcome_id: Option<u32>,
+    /// Bounds of a scalar market, whose outcomes are short (0) and long (1)
+    scalar_range: Option<ScalarRange>,
+    resolved_value: Option<i64>,
+    closes_at_timestamp: Timestamp,
+    token: ApplicationId,
+    /// For a conditional market, the parent outcome whose shares are its collateral
+    parent: Option<ParentPosition>,
+    /// The event grouping this market, if any
+    event_id: Option<u64>,
+    /// Collateral backing the outstanding complete sets
+    balance: Amount,
+    /// Total LP shares
+    liquidity: Amount,
+    /// LP fees collected since creation
+    fee_accumulator: Amount,
+    buy_fees: Fees,
+    sell_fees: Fees,
+    treasury: AccountOwner,
+    distributor: AccountOwner,
+    arbitrator: AccountOwner,
+    realitio_timeout: u32,
+    manager: AccountOwner,
+    creator: AccountOwner,
+    paused: bool,
+    outcomes: Vec<OutcomeView>,
+    /// Implied probability of each outcome, derived from the pools (1.0 == 100%)
+    prices: Vec<Amount>,
+    /// Redemption price of each outcome once the market is voided or a scalar market resolves
+    payout_prices: Vec<Amount>,
+}
+
+#[derive(SimpleObject)]
+struct OutcomeView {
+    id: u32,
+    label: String,
+    shares_total: Amount,
+    /// Pool size held by the market maker
+    shares_available: Amount,
+}
+
+impl From<Market> for MarketView {
+    fn from(m: Market) -> Self {
+        let prices = m.market_maker.prices(&pools(&m));
+        let lmsr_liquidity = match m.market_maker {
+            MarketMaker::Lmsr { b } => Some(Amount::from_attos(b)),
+            MarketMaker::Fpmm => None,
+        };
+        MarketView {
+            id: m.id,
+            question: m.question,
+            question_id: m.question_id,
+            image: m.image,
+            outcome_count: m.outcome_count,
+            state: m.state,
+            lmsr_liquidity,
+            resolved_outcome_id: m.resolved_outcome_id,
+            scalar_range: m.scalar_range,
+            resolved_value: m.resolved_value,
+            closes_at_timestamp: m.closes_at_timestamp,
+            token: m.token,
+            parent: m.parent,
+            event_id: m.event_id,
+            balance: m.balance,
+            liquidity: Amount::from_attos(m.liquidity),
+            fee_accumulator: Amount::from_attos(m.fee_accumulator),
+            buy_fees: m.buy_fees,
+            sell_fees: m.sell_fees,
+            treasury: m.treasury,
+            distributor: m.distributor,
+            arbitrator: m.arbitrator,
+            realitio_timeout: m.realitio_timeout,
+            manager: m.manager,
+            creator: m.creator,
+            paused: m.paused,
+            outcomes: m
+                .outcomes
+                .into_iter()
+                .map(|outcome| OutcomeView {
+                    id: outcome.id,
+                    label: outcome.label,
+                    shares_total: Amount::from_attos(outcome.shares_total),
+                    shares_available: Amount::from_attos(outcome.shares_available),
+                })
+                .collect(),
+            prices: prices.into_iter().map(Amount::from_attos).collect(),
+            payout_prices: m.payout_prices.into_iter().map(Amount::from_attos).collect(),
+        }
+    }
+}
+
+/// One page of a market listing
+#[derive(SimpleObject)]
+struct MarketConnection {
+    nodes: Vec<MarketView>,
+    page_info: PageInfo,
+}
+
+/// One page of a market's trade history
+#[derive(SimpleObject)]
+struct TradeConnection {
+    nodes: Vec<TradeRecord>,
+    page_info: PageInfo,
 }
 
+/// One page of the live updates feed
+#[derive(SimpleObject)]
+struct UpdateConnection {
+    nodes: Vec<MarketUpdate>,
+    page_info: PageInfo,
+}
+
+#[derive(SimpleObject)]
+struct PageInfo {
+    /// Cursor to pass as `after` to fetch the next page
+    end_cursor: Option<String>,
+    has_next_page: bool,
+}
+
+/// Bucket width of a price candle
+#[derive(async_graphql::Enum, Clone, Copy, PartialEq, Eq)]
+enum CandleInterval {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl CandleInterval {
+    fn micros(self) -> u64 {
+        match self {
+            CandleInterval::Minute => 60_000_000,
+            CandleInterval::Hour => 3_600_000_000,
+            CandleInterval::Day => 86_400_000_000,
+        }
+    }
+}
+
+/// OHLC prices of one outcome over a time bucket. Buckets without trades are skipped.
+#[derive(SimpleObject)]
+struct Candle {
+    start: Timestamp,
+    open: Amount,
+    high: Amount,
+    low: Amount,
+    close: Amount,
+    /// Tokens traded on this outcome during the bucket
+    volume: Amount,
+}
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
 #[derive(SimpleObject)]
 struct ShareView {
     market_id: u64,
     outcome_id: u32,
+    /// Empty if this chain never received a receipt naming the outcome
+    label: String,
     amount: String, 
 }
 
+/// Expected result of a trade, priced with the same math the contract executes
+#[derive(SimpleObject)]
+struct QuoteView {
+    /// Shares received by a buy, or given up by a sell
+    shares: Amount,
+    fees: FeeBreakdown,
+    /// Tokens paid per share on a buy, or received per share on a sell, after fees
+    average_price: Amount,
+    /// Relative move of the traded outcome's price (1.0 == 100%)
+    price_impact: Amount,
+    /// Implied probability of every outcome after the trade
+    prices: Vec<Amount>,
+}
+
+impl QuoteView {
+    fn new(
+        engine: &impl PricingEngine,
+        before: &[u128],
+        after: &[u128],
+        outcome_id: u32,
+        value: u128,
+        quote: &TradeQuote,
+    ) -> Self {
+        let price_before = engine.prices(before)[outcome_id as usize];
+        let prices = engine.prices(after);
+        let price_after = prices[outcome_id as usize];
+
+        let average_price = amm::mul_div(value, ONE, quote.shares).unwrap_or(0);
+        let price_impact = amm::mul_div(price_after.abs_diff(price_before), ONE, price_before).unwrap_or(0);
+
+        QuoteView {
+            shares: Amount::from_attos(quote.shares),
+            fees: FeeBreakdown {
+                fee: Amount::from_attos(quote.fee),
+                treasury_fee: Amount::from_attos(quote.treasury_fee),
+                distributor_fee: Amount::from_attos(quote.distributor_fee),
+            },
+            average_price: Amount::from_attos(average_price),
+            price_impact: Amount::from_attos(price_impact),
+            prices: prices.into_iter().map(Amount::from_attos).collect(),
+        }
+    }
+}
+
+fn pools(market: &Market) -> Vec<u128> {
+    market.outcomes.iter().map(|outcome| outcome.shares_available).collect()
+}
+
 struct QueryRoot;
 
 #[Object]
@@ -90,15 +302,293 @@ impl QueryRoot {
             .await
             .map_err(|e| async_graphql::Error::new(format!("Failed to load market: {e}")))?;
 
-        Ok(maybe.map(|m: Market| MarketView {
-            id: m.id,
-            question: m.question,
-            image: m.image,
-            outcome_count: m.outcome_count,
-            state: m.state,
+        Ok(maybe.map(MarketView::from))
+    }
+
+    /// Markets in creation order, filtered through the contract's listing indices
+    #[allow(clippy::too_many_arguments)]
+    async fn markets(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<usize>,
+        after: Option<String>,
+        state: Option<MarketState>,
+        creator: Option<AccountOwner>,
+        token: Option<ApplicationId>,
+        closing_before: Option<Timestamp>,
+    ) -> async_graphql::Result<MarketConnection> {
+        let root = ctx.data::<Arc<TruemarketState>>()?;
+        let view_error = |e: ViewError| async_graphql::Error::new(format!("Failed to load markets: {e}"));
+
+        let after = after
+            .map(|cursor| cursor.parse::<u64>())
+            .transpose()
+            .map_err(|_| async_graphql::Error::new("Invalid cursor"))?;
+        let first = first.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+        let start = after.map_or(0, |after| after + 1);
+
+        // One index yields the candidates, in creation order; the other filters are
+        // checked on each loaded market
+        let (index, prefix) = if let Some(creator) = creator {
+            (Some(&root.markets_by_creator), creator_prefix(&creator))
+        } else if let Some(token) = token {
+            (Some(&root.markets_by_token), token_prefix(&token))
+        } else if let Some(state) = state {
+            (Some(&root.markets_by_state), state_prefix(state))
+        } else {
+            (None, Vec::new())
+        };
+
+        let mut ids = Vec::new();
+        if let Some(before) = closing_before {
+            // Only the markets closing before `before` are scanned
+            let before = close_prefix(before);
+            root.markets_by_close
+                .for_each_key_while(|key| {
+                    let closing = key < before.as_slice();
+                    if closing {
+                        ids.push(index_market_id(key));
+                    }
+                    Ok(closing)
+                }, Vec::new())
+                .await
+                .map_err(view_error)?;
+            ids.retain(|id| *id >= start);
+            ids.sort_unstable();
+        } else if let Some(index) = index {
+            index
+                .for_each_key_while(|key| {
+                    let id = index_market_id(key);
+                    if id >= start {
+                        ids.push(id);
+                    }
+                    Ok(true)
+                }, prefix)
+                .await
+                .map_err(view_error)?;
+        } else {
+            ids.extend((start..*root.market_index.get()).take(first + 1));
+        }
+
+        let mut nodes = Vec::new();
+        let mut has_next_page = false;
+        for id in ids {
+            let Some(market) = root.markets.get(&id).await.map_err(view_error)? else {
+                continue;
+            };
+            let matches = state.is_none_or(|state| market.state == state)
+                && creator.is_none_or(|creator| market.creator == creator)
+                && token.is_none_or(|token| market.token == token);
+            if !matches {
+                continue;
+            }
+            if nodes.len() == first {
+                has_next_page = true;
+                break;
+            }
+            nodes.push(MarketView::from(market));
+        }
+
+        Ok(MarketConnection {
+            page_info: PageInfo {
+                end_cursor: nodes.last().map(|market| market.id.to_string()),
+                has_next_page,
+            },
+            nodes,
+        })
+    }
+
+    /// A market's trades, oldest first
+    async fn trades(
+        &self,
+        ctx: &Context<'_>,
+        market_id: u64,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> async_graphql::Result<TradeConnection> {
+        let state = ctx.data::<Arc<TruemarketState>>()?;
+        let view_error = |e: ViewError| async_graphql::Error::new(format!("Failed to load trades: {e}"));
+
+        let trade_count = state
+            .markets
+            .get(&market_id)
+            .await
+            .map_err(view_error)?
+            .map_or(0, |market| market.trade_count);
+
+        let start = match after {
+            Some(cursor) => cursor.parse::<u64>().map_err(|_| async_graphql::Error::new("Invalid cursor"))? + 1,
+            None => 0,
+        };
+        let first = first.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE) as u64;
+        let end = start.saturating_add(first).min(trade_count);
+
+        let mut nodes = Vec::new();
+        for id in start..end {
+            if let Some(trade) = state.trades.get(&(market_id, id)).await.map_err(view_error)? {
+                nodes.push(trade);
+            }
+        }
+
+        Ok(TradeConnection {
+            page_info: PageInfo {
+                end_cursor: nodes.last().map(|trade| trade.id.to_string()),
+                has_next_page: end < trade_count,
+            },
+            nodes,
+        })
+    }
+
+    /// Price candles of one outcome, built from the market's trades
+    async fn candles(
+        &self,
+        ctx: &Context<'_>,
+        market_id: u64,
+        outcome_id: u32,
+        interval: CandleInterval,
+        from: Option<Timestamp>,
+        to: Option<Timestamp>,
+    ) -> async_graphql::Result<Vec<Candle>> {
+        let state = ctx.data::<Arc<TruemarketState>>()?;
+        let view_error = |e: ViewError| async_graphql::Error::new(format!("Failed to load prices: {e}"));
+        let missing = || async_graphql::Error::new("Missing trade");
+
+        let Some(market) = state.markets.get(&market_id).await.map_err(view_error)? else {
+            return Ok(Vec::new());
+        };
+        if outcome_id >= market.outcome_count {
+            return Err(async_graphql::Error::new("Invalid outcome"));
+        }
+
+        // Trades are appended in time order, so binary-search the first one in range
+        let count = market.trade_count;
+        let mut start = 0;
+        if let Some(from) = from {
+            let mut end = count;
+            while start < end {
+                let mid = start + (end - start) / 2;
+                let trade = state.trades.get(&(market_id, mid)).await.map_err(view_error)?
+                    .ok_or_else(missing)?;
+                if trade.timestamp < from {
+                    start = mid + 1;
+                } else {
+                    end = mid;
+                }
+            }
+        }
+
+        let mut candles: Vec<Candle> = Vec::new();
+        for index in start..count {
+            let trade = state.trades.get(&(market_id, index)).await.map_err(view_error)?
+                .ok_or_else(missing)?;
+            if to.is_some_and(|to| trade.timestamp >= to) {
+                break;
+            }
+
+            let price = trade.prices[outcome_id as usize];
+            let micros = trade.timestamp.micros();
+            let bucket = Timestamp::from(micros - micros % interval.micros());
+            let volume = if trade.outcome_id == outcome_id { trade.value } else { Amount::ZERO };
+
+            match candles.last_mut() {
+                Some(candle) if candle.start == bucket => {
+                    candle.high = candle.high.max(price);
+                    candle.low = candle.low.min(price);
+                    candle.close = price;
+                    candle.volume = candle.volume.saturating_add(volume);
+                }
+                _ => candles.push(Candle {
+                    start: bucket,
+                    open: price,
+                    high: price,
+                    low: price,
+                    close: price,
+                    volume,
+                }),
+            }
+        }
+
+        Ok(candles)
+    }
+
+    /// Quote for buying `outcome_id` with `value` tokens, fees included
+    async fn quote_buy(
+        &self,
+        ctx: &Context<'_>,
+        market_id: u64,
+        outcome_id: u32,
+        value: Amount,
+    ) -> async_graphql::Result<Option<QuoteView>> {
+        let state = ctx.data::<Arc<TruemarketState>>()?;
+        let Some(market) = state
+            .markets
+            .get(&market_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Failed to load market: {e}")))?
+        else {
+            return Ok(None);
+        };
+
+        let before = pools(&market);
+        let value = u128::from(value);
+        let engine = &market.market_maker;
+        Ok(amm::quote_buy(engine, &before, &market.buy_fees, outcome_id, value).map(|quote| {
+            let after = amm::pools_after_buy(&before, outcome_id, &quote);
+            QuoteView::new(engine, &before, &after, outcome_id, value, &quote)
         }))
     }
 
+    /// Quote for selling enough `outcome_id` shares to receive `value` tokens after fees
+    async fn quote_sell(
+        &self,
+        ctx: &Context<'_>,
+        market_id: u64,
+        outcome_id: u32,
+        value: Amount,
+    ) -> async_graphql::Result<Option<QuoteView>> {
+        let state = ctx.data::<Arc<TruemarketState>>()?;
+        let Some(market) = state
+            .markets
+            .get(&market_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Failed to load market: {e}")))?
+        else {
+            return Ok(None);
+        };
+
+        let before = pools(&market);
+        let value = u128::from(value);
+        let engine = &market.market_maker;
+        Ok(amm::quote_sell(engine, &before, &market.sell_fees, outcome_id, value).map(|quote| {
+            let after = amm::pools_after_sell(&before, outcome_id, &quote);
+            QuoteView::new(engine, &before, &after, outcome_id, value, &quote)
+        }))
+    }
+
+    /// An event and the ids of the markets it groups
+    async fn event(&self, ctx: &Context<'_>, event_id: u64) -> async_graphql::Result<Option<Event>> {
+        let state = ctx.data::<Arc<TruemarketState>>()?;
+        state
+            .events
+            .get(&event_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Failed to load event: {e}")))
+    }
+
+    /// Oracle answers posted for a market, if any
+    async fn question(
+        &self,
+        ctx: &Context<'_>,
+        market_id: u64,
+    ) -> async_graphql::Result<Option<Question>> {
+        let state = ctx.data::<Arc<TruemarketState>>()?;
+        state
+            .questions
+            .get(&market_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Failed to load question: {e}")))
+    }
+
     /// Fetch the authenticated user's shares
     async fn my_shares(
         &self,
@@ -108,9 +598,14 @@ impl QueryRoot {
         let state = ctx.data::<Arc<TruemarketState>>()?;
         let mut results = Vec::new();
 
-        // FIX: We cannot load `state.markets.get(&market_id)` here because
-        // the User Chain doesn't have the market definition.
-        // Instead, we blindly check all possible outcome slots.
+        // The User Chain doesn't have the market definition, so `market` is only
+        // found on the market chain. Labels otherwise come from the share receipts,
+        // and we blindly check all possible outcome slots.
+        let market = state
+            .markets
+            .get(&market_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("E: {e}")))?;
         
         for outcome_id in 0..MAX_OUTCOMES {
             let key = (market_id, outcome_id);
@@ -124,9 +619,19 @@ impl QueryRoot {
                 .unwrap_or(0);
 
             if amount > 0 {
+                let label = match market.as_ref().and_then(|m| m.outcomes.get(outcome_id as usize)) {
+                    Some(outcome) => outcome.label.clone(),
+                    None => state
+                        .outcome_labels
+                        .get(&key)
+                        .await
+                        .map_err(|e| async_graphql::Error::new(format!("E: {e}")))?
+                        .unwrap_or_default(),
+                };
                 results.push(ShareView {
                     market_id,
                     outcome_id,
+                    label,
                     amount: amount.to_string(),
                 });
             }
@@ -134,4 +639,83 @@ impl QueryRoot {
 
         Ok(results)
     }
-}
\ No newline at end of file
+
+    /// Remote trades from this chain that bounced and were refunded
+    async fn failed_trades(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<TradeFailure>> {
+        let state = ctx.data::<Arc<TruemarketState>>()?;
+        let count = state.failed_trades.count();
+        state
+            .failed_trades
+            .read(0..count)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Failed to load failed trades: {e}")))
+    }
+
+    /// Manager changes, oldest first, optionally restricted to one market
+    async fn audit_log(
+        &self,
+        ctx: &Context<'_>,
+        market_id: Option<u64>,
+    ) -> async_graphql::Result<Vec<AuditEntry>> {
+        let state = ctx.data::<Arc<TruemarketState>>()?;
+        let count = state.audit_log.count();
+        let entries = state
+            .audit_log
+            .read(0..count)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Failed to load audit log: {e}")))?;
+
+        Ok(entries
+            .into_iter()
+            .filter(|entry| market_id.is_none_or(|id| entry.market_id == id))
+            .collect())
+    }
+
+    /// Market events and share changes seen by this chain, oldest first. Clients listen to the
+    /// chain's block notifications and fetch the entries after the last cursor they saw.
+    async fn updates(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<usize>,
+        after: Option<String>,
+        market_id: Option<u64>,
+    ) -> async_graphql::Result<UpdateConnection> {
+        let state = ctx.data::<Arc<TruemarketState>>()?;
+        let count = state.updates.count();
+
+        let start = match after {
+            Some(cursor) => cursor.parse::<usize>().map_err(|_| async_graphql::Error::new("Invalid cursor"))? + 1,
+            None => 0,
+        };
+        let first = first.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+
+        // Read in pages until enough entries of the requested market were found
+        let mut nodes = Vec::new();
+        let mut next = start;
+        while next < count && nodes.len() < first {
+            let end = next.saturating_add(MAX_PAGE_SIZE).min(count);
+            let updates = state
+                .updates
+                .read(next..end)
+                .await
+                .map_err(|e| async_graphql::Error::new(format!("Failed to load updates: {e}")))?;
+            for update in updates {
+                next += 1;
+                if market_id.is_none_or(|id| update.market_id == id) {
+                    nodes.push(update);
+                    if nodes.len() == first {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(UpdateConnection {
+            page_info: PageInfo {
+                end_cursor: nodes.last().map(|update| update.id.to_string()),
+                has_next_page: next < count,
+            },
+            nodes,
+        })
+    }
+}
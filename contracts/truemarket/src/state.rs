@@ -1,9 +1,15 @@
 use serde::{Deserialize, Serialize};
 
-use linera_sdk::views::{linera_views, MapView, RegisterView, RootView, ViewStorageContext};
+use async_graphql::SimpleObject;
+use linera_sdk::views::{linera_views, LogView, MapView, RegisterView, RootView, ViewStorageContext};
+use linera_sdk::views::linera_views::map_view::ByteMapView;
 use linera_sdk::linera_base_types::{ApplicationId, AccountOwner, Timestamp, Amount};
 
-use truemarket::{Fees, MarketState};
+use truemarket::amm::MarketMaker;
+use truemarket::{
+    FeeBreakdown, Fees, MarketAction, MarketState, ParentPosition, ScalarRange, TradeAction,
+    UpdateKind,
+};
 
 #[derive(RootView)]
 #[view(context = ViewStorageContext)]
@@ -22,6 +28,61 @@ pub struct TruemarketState {
 
     #[view(default)]
     pub my_shares: MapView<(u64, u32), u128>,
+
+    /// Outcome labels learned from share receipts: (Market ID, Outcome ID) -> Label
+    #[view(default)]
+    pub outcome_labels: MapView<(u64, u32), String>,
+
+    /// Listing indices, one key per market (see `truemarket::index`), scanned by filter prefix
+    #[view(default)]
+    pub markets_by_state: ByteMapView<ViewStorageContext, ()>,
+    #[view(default)]
+    pub markets_by_creator: ByteMapView<ViewStorageContext, ()>,
+    #[view(default)]
+    pub markets_by_token: ByteMapView<ViewStorageContext, ()>,
+    /// Closing time index, scanned from the earliest closing market
+    #[view(default)]
+    pub markets_by_close: ByteMapView<ViewStorageContext, ()>,
+
+    /// LP shares: (Market ID, AccountOwner) -> Liquidity Share Amount
+    #[view(default)]
+    pub liquidity_shares: MapView<(u64, AccountOwner), u128>,
+
+    /// LP fees already accounted for: (Market ID, AccountOwner) -> Fee Debt
+    #[view(default)]
+    pub fee_debt: MapView<(u64, AccountOwner), u128>,
+
+    /// Claimed winnings: (Market ID, AccountOwner) -> Claimed
+    #[view(default)]
+    pub claimed: MapView<(u64, AccountOwner), bool>,
+
+    /// Trade history: (Market ID, Trade ID) -> Trade
+    #[view(default)]
+    pub trades: MapView<(u64, u64), TradeRecord>,
+
+    /// Total number of events created.
+    #[view(default)]
+    pub event_index: RegisterView<u64>,
+
+    /// Events grouping related markets: Event ID -> Event
+    #[view(default)]
+    pub events: MapView<u64, Event>,
+
+    /// Oracle questions: Market ID -> Answer history
+    #[view(default)]
+    pub questions: MapView<u64, Question>,
+
+    /// Remote trades from this chain that the market chain rejected.
+    #[view(default)]
+    pub failed_trades: LogView<TradeFailure>,
+
+    /// Every change made by a market manager, in order.
+    #[view(default)]
+    pub audit_log: LogView<AuditEntry>,
+
+    /// Market events seen by this chain and changes to `my_shares`, in order.
+    #[view(default)]
+    pub updates: LogView<MarketUpdate>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -32,22 +93,39 @@ pub struct Market {
     pub liquidity: u128,
     pub shares_available: u128,
     pub state: MarketState,
+    pub market_maker: MarketMaker,
 
     pub buy_fees: Fees,
     pub sell_fees: Fees,
     pub treasury: AccountOwner,
     pub distributor: AccountOwner,
     pub fee_accumulator: u128,
+    /// LP fees earned per LP share since creation (scaled by `ONE`).
+    pub fees_per_share: u128,
+    /// Number of trades recorded in `TruemarketState::trades`.
+    pub trade_count: u64,
 
     pub question: String,
     pub question_id: String,
     pub arbitrator: AccountOwner,
     pub realitio_timeout: u32,
+    pub resolved_outcome_id: Option<u32>,
+    /// Set for scalar markets, which have exactly two outcomes: short and long.
+    pub scalar_range: Option<ScalarRange>,
+    pub resolved_value: Option<i64>,
+    /// Redemption price per share of each outcome once voided or once a scalar market
+    /// resolves (scaled by `ONE`).
+    pub payout_prices: Vec<u128>,
 
     pub outcome_count: u32,
     pub outcomes: Vec<MarketOutcome>,
 
     pub token: ApplicationId,
+    /// Set for conditional markets, whose collateral is a position in `parent` rather than
+    /// `token` itself.
+    pub parent: Option<ParentPosition>,
+    /// The event this market is grouped in, which then resolves it.
+    pub event_id: Option<u64>,
     pub manager: AccountOwner,
     pub creator: AccountOwner,
     pub paused: bool,
@@ -57,6 +135,94 @@ pub struct Market {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct MarketOutcome {
     pub id: u32,
+    pub label: String,
     pub shares_total: u128,
     pub shares_available: u128,
 }
+
+/// Related yes/no markets presented as one event, of which exactly one resolves to yes.
+#[derive(Debug, Deserialize, Serialize, Clone, SimpleObject)]
+pub struct Event {
+    pub id: u64,
+    pub title: String,
+    pub closes_at: Timestamp,
+    pub market_ids: Vec<u64>,
+    pub token: ApplicationId,
+    pub arbitrator: AccountOwner,
+    pub manager: AccountOwner,
+    /// The market that resolved to yes.
+    pub resolved_market_id: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, SimpleObject)]
+pub struct AuditEntry {
+    pub market_id: u64,
+    pub action: MarketAction,
+    pub manager: AccountOwner,
+    pub timestamp: Timestamp,
+    /// JSON encoding of the value before and after the change.
+    pub previous: String,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, SimpleObject)]
+pub struct Question {
+    /// Every answer given so far, the current best answer last.
+    pub answers: Vec<Answer>,
+    /// When the best answer becomes final unless challenged.
+    pub finalizes_at: Timestamp,
+    pub arbitration_requested_by: Option<AccountOwner>,
+    /// Paid by the arbitration requester, goes to the arbitrator with the ruling.
+    pub arbitration_fee: Amount,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, SimpleObject)]
+pub struct Answer {
+    pub answerer: AccountOwner,
+    pub outcome_id: u32,
+    pub bond: Amount,
+}
+
+/// Receipt kept by a user chain when one of its remote trades bounced.
+#[derive(Debug, Deserialize, Serialize, Clone, SimpleObject)]
+pub struct TradeFailure {
+    pub action: TradeAction,
+    pub market_id: u64,
+    /// The traded outcome; `None` for claims.
+    pub outcome_id: Option<u32>,
+    pub owner: AccountOwner,
+    pub value: Amount,
+    pub timestamp: Timestamp,
+}
+
+/// One buy or sell, as shown in a market's trade feed.
+#[derive(Debug, Deserialize, Serialize, Clone, SimpleObject)]
+pub struct TradeRecord {
+    /// Position in the market's history, also used as the pagination cursor.
+    pub id: u64,
+    pub action: TradeAction,
+    pub trader: AccountOwner,
+    pub outcome_id: u32,
+    /// Tokens paid in for a buy, or received for a sell.
+    pub value: Amount,
+    pub shares: Amount,
+    pub fees: FeeBreakdown,
+    pub timestamp: Timestamp,
+    /// Implied probability of every outcome after the trade.
+    pub prices: Vec<Amount>,
+}
+
+/// One entry of the live feed clients poll after a new block notification.
+#[derive(Debug, Deserialize, Serialize, Clone, SimpleObject)]
+pub struct MarketUpdate {
+    /// Position in the feed, also used as the pagination cursor.
+    pub id: u64,
+    pub market_id: u64,
+    pub kind: UpdateKind,
+    pub timestamp: Timestamp,
+    pub outcome_id: Option<u32>,
+    /// Trade value, LP shares or claimed amount; the new balance for `SharesChanged`.
+    pub amount: Option<Amount>,
+    /// Outcome prices after the change, when it moved them.
+    pub prices: Vec<Amount>,
+}